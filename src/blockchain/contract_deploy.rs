@@ -12,11 +12,9 @@ use std::time::Duration;
 
 pub type SignerDeployedContract<T> = Contract<SignerMiddleware<Provider<T>, LocalWallet>>;
 
-pub async fn compile_deploy_contract() -> Result<()> {
+pub async fn compile_deploy_contract(mnemonic: &str) -> Result<()> {
     println!("------------------== compile_deploy_contract");
     // Spawn a ganache instance
-    let mnemonic = dotenvy::var("MNEMONIC").expect("MNEMONIC not found");
-    println!("MNEMONIC is valid: {}", &mnemonic);
 
     let ganache = Ganache::new().mnemonic(mnemonic).spawn();
     println!("HTTP Endpoint: {}", ganache.endpoint());
@@ -5,7 +5,7 @@ use ethers::{
     utils::{format_units, parse_ether},
 };
 use ethers_providers::{Authorization, Http};
-use eyre::Result;
+use eyre::{eyre, Result};
 use reqwest::header::{HeaderMap, HeaderValue};
 use std::{
     error::Error,
@@ -13,8 +13,10 @@ use std::{
     str::FromStr,
     sync::Arc,
 };
+use tokio_util::sync::CancellationToken;
 
-use crate::blockchain::wallet::{self,  get_address_from_mnemonic, make_new_mnemonic};
+use crate::blockchain::wallet::{self, get_address_from_mnemonic, make_new_mnemonic};
+use crate::config::ChainConfig;
 
 async fn _create_instance(rpc_url: &str) -> eyre::Result<()> {
     // An Http provider can be created from an http(s) URI.
@@ -82,109 +84,6 @@ abigen!(ERC20Token, "src/blockchain/ERC20Token.json");
      function getReserves() external view returns (uint112 reserve0, uint112 reserve1, uint32 blockTimestampLast)
  ]"#
 */
-#[derive(Debug)]
-pub struct Env {
-    pub mnemonic: String,
-    pub network: String,
-    pub rpc_url: String,
-    pub pvkey0: String,
-    pub addr0: H160,
-    pub addr1: H160,
-    pub addr2: H160,
-    pub addr3: H160,
-    pub erc20_token_addr: H160,
-    pub chainlink_aggr_v3_btcusd: H160,
-    pub chainlink_aggr_v3_ethusd: H160,
-}
-pub fn get_env_parameters() -> Result<Env, String> {
-    // MNEMONIC has to be enclosed in double quotes for .env to work!!!
-    let mnemonic = dotenvy::var("MNEMONIC").map_err(|_e| "MNEMONIC not found".to_owned())?;
-    println!("MNEMONIC is valid: {}", &mnemonic);
-
-    let network = dotenvy::var("NETWORK").map_err(|_e| "NETWORK not found".to_owned())?;
-    println!("NETWORK is valid: {}", &network);
-
-    let infura_key = dotenvy::var("INFURA_KEY").map_err(|_e| "INFURA_KEY not found".to_owned())?;
-    println!("INFURA_KEY is valid: {}", &infura_key);
-
-    let rpc_url = [
-        "https://",
-        network.as_str(),
-        ".infura.io/v3/",
-        infura_key.as_str(),
-    ]
-    .join("");
-    println!("rpc_url: {}", &rpc_url);
-
-    let pvkey0 = dotenvy::var("PRIVATE_KEY0").map_err(|_e| "PRIVATE_KEY0 not found".to_owned())?;
-    println!("PRIVATE_KEY0 is valid: {}", &pvkey0);
-
-    let addr0 = dotenvy::var("ETH_ADDR0").map_err(|_e| "ETH_ADDR0 not found".to_owned())?;
-    println!("ETH_ADDR0 is valid: {}", &addr0);
-    let addr0 = addr0
-        .parse::<Address>()
-        .map_err(|_e| "addr0 parse error".to_owned())?;
-    //Address::from_str("0x...").expect();
-
-    let addr1 = dotenvy::var("ETH_ADDR1").map_err(|_e| "ETH_ADDR1 not found".to_owned())?;
-    println!("ETH_ADDR1 is valid: {}", &addr1);
-    let addr1 = addr1
-        .parse::<Address>()
-        .map_err(|_e| "addr1 parse error".to_owned())?;
-
-    let addr2 = dotenvy::var("ETH_ADDR2").map_err(|_e| "ETH_ADDR2 not found".to_owned())?;
-    println!("ETH_ADDR2 is valid: {}", &addr2);
-    let addr2 = addr2
-        .parse::<Address>()
-        .map_err(|_e| "addr2 parse error".to_owned())?;
-
-    let addr3 = dotenvy::var("ETH_ADDR3").map_err(|_e| "ETH_ADDR3 not found".to_owned())?;
-    println!("ETH_ADDR3 is valid: {}", &addr3);
-    let addr3 = addr3
-        .parse::<Address>()
-        .map_err(|_e| "addr3 parse error".to_owned())?;
-
-    let erc20_token_addr =
-        dotenvy::var("ETH_ERC20TOKEN").map_err(|_e| "ETH_ERC20TOKEN not found".to_owned())?;
-    println!("ETH_ERC20TOKEN is valid: {}", &erc20_token_addr);
-    let erc20_token_addr = erc20_token_addr
-        .parse::<Address>()
-        .map_err(|_e| "erc20_token_addr parse error".to_owned())?;
-
-    let chainlink_aggr_v3_btcusd = dotenvy::var("CHAINLINK_AGGR_V3_BTCUSD")
-        .map_err(|_e| "CHAINLINK_AGGR_V3_BTCUSD not found".to_owned())?;
-    println!(
-        "CHAINLINK_AGGR_V3_BTCUSD is valid: {}",
-        &chainlink_aggr_v3_btcusd
-    );
-    let chainlink_aggr_v3_btcusd = chainlink_aggr_v3_btcusd
-        .parse::<Address>()
-        .map_err(|_e| "chainlink_aggr_v3_btcusd parse error".to_owned())?;
-
-    let chainlink_aggr_v3_ethusd = dotenvy::var("CHAINLINK_AGGR_V3_ETHUSD")
-        .map_err(|_e| "CHAINLINK_AGGR_V3_ETHUSD not found".to_owned())?;
-    println!(
-        "CHAINLINK_AGGR_V3_ETHUSD is valid: {}",
-        &chainlink_aggr_v3_ethusd
-    );
-    let chainlink_aggr_v3_ethusd = chainlink_aggr_v3_ethusd
-        .parse::<Address>()
-        .map_err(|_e| "chainlink_aggr_v3_ethusd parse error".to_owned())?;
-
-    Ok(Env {
-        mnemonic,
-        network,
-        rpc_url,
-        pvkey0,
-        addr0,
-        addr1,
-        addr2,
-        addr3,
-        erc20_token_addr,
-        chainlink_aggr_v3_btcusd,
-        chainlink_aggr_v3_ethusd,
-    })
-}
 pub async fn get_write_provider(
     rpc_url: &str,
     private_key: &str,
@@ -217,25 +116,26 @@ pub async fn get_write_provider(
     });
     Ok(provider)
 }
-pub async fn ethereum_live_write(amount_in_eth: f64) -> Result<(String, String)> {
+pub async fn ethereum_live_write(
+    chain: &ChainConfig,
+    amount_in_eth: f64,
+    shutdown: &CancellationToken,
+) -> Result<(String, String)> {
     println!("ethereum_live_write 0");
-    let env = get_env_parameters().expect("env error");
-    let provider = get_write_provider(&env.rpc_url, &env.pvkey0)
+    let provider = get_write_provider(&chain.rpc_url, &chain.pvkey0)
         .await
         .expect("provider error");
     println!("ethereum_live_write 1");
 
-    let erc20token = ERC20Token::new(env.erc20_token_addr, Arc::clone(&provider));
+    let erc20token = ERC20Token::new(chain.erc20_token_addr, Arc::clone(&provider));
     println!("ethereum_live_write 2");
-    let balance0 = erc20token.balance_of(env.addr0).call().await?;
+    let balance0 = erc20token.balance_of(chain.addr0).call().await?;
     println!("balance0: ({balance0})");
-    let balance1 = erc20token.balance_of(env.addr1).call().await?;
+    let balance1 = erc20token.balance_of(chain.addr1).call().await?;
     println!("balance1: ({balance1})");
     //let amount_in_eth = 17u64;
-    let receipt = erc20token
-        .transfer(env.addr1, parse_ether(amount_in_eth)?)
-        .send()
-        .await?
+    let transfer = erc20token.transfer(chain.addr1, parse_ether(amount_in_eth)?);
+    let receipt = wait_for_receipt(transfer.send().await?, shutdown)
         .await?
         .expect("no receipt found");
     println!("receipt made");
@@ -251,14 +151,27 @@ pub async fn ethereum_live_write(amount_in_eth: f64) -> Result<(String, String)>
     println!("tx confirmed: {}\n", serde_json::to_string(&tx)?);
     println!("receipt: {}", serde_json::to_string(&receipt)?);
 
-    let balance0 = erc20token.balance_of(env.addr0).call().await?;
+    let balance0 = erc20token.balance_of(chain.addr0).call().await?;
     println!("balance0: ({balance0})");
-    let balance1 = erc20token.balance_of(env.addr1).call().await?;
+    let balance1 = erc20token.balance_of(chain.addr1).call().await?;
     println!("balance1: ({balance1})");
     let bal1 = format_units(balance1, "ether").unwrap();
     Ok((txn_hash.to_string(), bal1))
 }
 
+/// Wait for the receipt of a broadcast txn, or stop waiting when the shutdown drain runs out.
+/// The txn may still be mined afterwards, so its hash is reported in the error
+async fn wait_for_receipt<P: JsonRpcClient>(
+    pending_tx: PendingTransaction<'_, P>,
+    shutdown: &CancellationToken,
+) -> Result<Option<TransactionReceipt>> {
+    let txn_hash = NewH256(*pending_tx);
+    tokio::select! {
+        receipt = pending_tx => Ok(receipt?),
+        _ = shutdown.cancelled() => Err(eyre!("shutting down before the receipt of txn {txn_hash} arrived")),
+    }
+}
+
 struct NewH256(H256);
 impl fmt::Display for NewH256 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -277,17 +190,15 @@ pub fn get_read_provider(rpc_url: &str) -> Result<Arc<Provider<Http>>, String> {
     let provider = Arc::new(prov);
     Ok(provider)
 }
-pub async fn ethereum_live_read() -> Result<(String, String)> {
+pub async fn ethereum_live_read(chain: &ChainConfig) -> Result<(String, String)> {
     //create_instance(&rpc_url).await?;
     //println!("create_instance succeeded");
     //share_providers_across_tasks().await?;
     //println!("share_providers_across_tasks succeeded");
     println!("ethereum_live_read 0");
-    let env = get_env_parameters().expect("env error");
-    //.map_err(|_e| "env error".to_owned())?;
     println!("ethereum_live_read 1");
-    let provider = get_read_provider(&env.rpc_url).expect("provider error");
-    //let client = Provider::<Http>::try_from(env.rpc_url)?;
+    let provider = get_read_provider(&chain.rpc_url).expect("provider error");
+    //let client = Provider::<Http>::try_from(chain.rpc_url)?;
     //let client = Arc::new(client);
     println!("ethereum_live_read 2");
 
@@ -299,7 +210,7 @@ pub async fn ethereum_live_read() -> Result<(String, String)> {
         .unwrap();
     println!("last_block: {last_block}");
 
-    let erc20token = ERC20Token::new(env.erc20_token_addr, Arc::clone(&provider));
+    let erc20token = ERC20Token::new(chain.erc20_token_addr, Arc::clone(&provider));
     println!("ethereum_live_read 3");
 
     let ctrt_name: String = erc20token.name().call().await?;
@@ -307,9 +218,9 @@ pub async fn ethereum_live_read() -> Result<(String, String)> {
     let total_supply: U256 = erc20token.total_supply().call().await?;
     println!("total_supply: ({total_supply})");
 
-    let balance0: U256 = erc20token.balance_of(env.addr0).call().await?;
+    let balance0: U256 = erc20token.balance_of(chain.addr0).call().await?;
     println!("balance0: ({balance0})");
-    let balance1: U256 = erc20token.balance_of(env.addr1).call().await?;
+    let balance1: U256 = erc20token.balance_of(chain.addr1).call().await?;
     println!("balance1: ({balance1})");
     let bal0 = format_units(balance0, "ether").unwrap();
     let bal1 = format_units(balance1, "ether").unwrap();
@@ -325,20 +236,21 @@ pub async fn ethereum_live_read() -> Result<(String, String)> {
 }
 
 pub async fn ethereum_send_ether(
+    chain: &ChainConfig,
     _to_addr_str: String,
     amount_in_eth: f64,
+    shutdown: &CancellationToken,
 ) -> Result<(String, String)> {
     println!("----------== ethereum_send_ether");
     println!("ethereum_send_ether 0");
-    let env = get_env_parameters().expect("env error");
-    let provider = get_write_provider(&env.rpc_url, &env.pvkey0)
+    let provider = get_write_provider(&chain.rpc_url, &chain.pvkey0)
         .await
         .expect("provider error");
     println!("ethereum_send_ether 1");
 
     //let accounts = provider.get_accounts().await?;
-    let from = env.addr0;
-    let to_addr = env.addr1;
+    let from = chain.addr0;
+    let to_addr = chain.addr1;
     //let to_addr = to_addr_str.parse::<Address>()?;
 
     let amount_in_u256 = parse_ether(amount_in_eth)?;
@@ -355,7 +267,7 @@ pub async fn ethereum_send_ether(
     dbg!(balance_before, nonce1);
 
     // broadcast it via the eth_sendTransaction API
-    let pending_tx = provider.send_transaction(tx, None).await?.await?;
+    let pending_tx = wait_for_receipt(provider.send_transaction(tx, None).await?, shutdown).await?;
     println!("ethereum_send_ether 4: tx pending");
     println!("{}", serde_json::to_string(&pending_tx)?);
 
@@ -389,10 +301,9 @@ pub async fn ethereum_send_ether(
 abigen!(ChainlinkAggrV3, "src/blockchain/chainlinkAggrV3.json");
 const ETH_DECIMALS: u32 = 18;
 const USD_PRICE_DECIMALS: u32 = 8;
-pub async fn get_chainlink_prices() -> Result<(String, String), String> {
+pub async fn get_chainlink_prices(chain: &ChainConfig) -> Result<(String, String), String> {
     println!("----------== get_chainlink_prices");
-    let env = get_env_parameters().expect("env error");
-    let provider = get_read_provider(&env.rpc_url).expect("provider error");
+    let provider = get_read_provider(&chain.rpc_url).expect("provider error");
     println!("get_chainlink_prices 0");
     let gas_in_wei: U256 = provider
         .get_gas_price()
@@ -405,11 +316,11 @@ pub async fn get_chainlink_prices() -> Result<(String, String), String> {
         .parse::<f64>()
         .map_err(|_e| "error2".to_owned())?;
 
-    let btc_price = get_chainlink(&provider, env.chainlink_aggr_v3_btcusd, "btc").await?;
+    let btc_price = get_chainlink(&provider, chain.chainlink_aggr_v3_btcusd, "btc").await?;
     println!("btc_price: {}", btc_price);
     let btc_price_str = format_units(btc_price, USD_PRICE_DECIMALS).unwrap();
 
-    let eth_price = get_chainlink(&provider, env.chainlink_aggr_v3_ethusd, "eth").await?;
+    let eth_price = get_chainlink(&provider, chain.chainlink_aggr_v3_ethusd, "eth").await?;
     println!("eth_price: {}", eth_price);
     let eth_price_str = format_units(eth_price, USD_PRICE_DECIMALS).unwrap();
 
@@ -7,14 +7,8 @@ use ethers::{
 use eyre::{ContextCompat, Result};
 use hex::ToHex;
 
-pub async fn ethereum_local_txn() -> Result<()> {
-    // Spawn a ganache instance ... MNEMONIC has to be enclosed in double quotes for .env to work!!!
-    let mnemonic = dotenvy::var("MNEMONIC").expect("MNEMONIC not found");
-    println!("MNEMONIC is valid: {}", &mnemonic);
-
-    let addr1 = dotenvy::var("ETH_ADDR1").expect("ETH_ADDR1 not found");
-    println!("ETH_ADDR1 is valid: {}", &addr1);
-
+pub async fn ethereum_local_txn(mnemonic: &str, other_address: Address) -> Result<()> {
+    // Spawn a ganache instance
     // Ganache-cli has to be installed globally first!
     // npm install ganache --global
     let ganache = Ganache::new().mnemonic(mnemonic).spawn();
@@ -39,8 +33,7 @@ pub async fn ethereum_local_txn() -> Result<()> {
     println!("Wallet first address balance: {}", first_balance);
 
     // Query the blance of some random account
-    let other_address_hex = addr1;
-    let other_address = other_address_hex.parse::<Address>()?;
+    let other_address_hex = other_address.encode_hex::<String>();
     let other_balance = provider.get_balance(other_address, None).await?;
     println!("Balance of {}: {}", other_address_hex, other_balance);
 
@@ -0,0 +1,286 @@
+//! Command line interface. Running without a subcommand is the same as `serve`
+use clap::{Args, Parser, Subcommand};
+use eyre::{bail, eyre, Result, WrapErr};
+use sea_orm::DatabaseConnection;
+use std::{
+    io::{self, BufRead, Write},
+    net::IpAddr,
+    time::Duration,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    blockchain::{contract_deploy::*, simple_txn_live::*},
+    config::{ChainConfig, Config, ConfigArgs, ServerConfig},
+    database::connect_db,
+    entities::users::Model as UserModel,
+    migration::{Migrator, MigratorTrait},
+    rbac::Role,
+    repository::{
+        orm::{SeaOrmApiKeyRepository, SeaOrmSessionRepository, SeaOrmUserRepository},
+        ApiKeyRepository, NewUser, SessionRepository, UserRepository,
+    },
+    utils::hash_password,
+    validation::{validate_password, validate_username},
+};
+
+/// Axum API server for users, tasks and Ethereum operations
+#[derive(Parser, Debug)]
+#[command(version)]
+pub struct Cli {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run the HTTP server
+    Serve(ServeArgs),
+    /// Manage database migrations
+    #[command(subcommand)]
+    Migrate(MigrateCommand),
+    /// Administer user accounts
+    #[command(subcommand)]
+    User(UserCommand),
+    /// Run blockchain operations with the configured wallet
+    #[command(subcommand)]
+    Chain(ChainCommand),
+}
+
+#[derive(Args, Debug, Default)]
+pub struct ServeArgs {
+    /// Address to bind to
+    #[arg(long)]
+    pub host: Option<IpAddr>,
+    /// Port to listen on
+    #[arg(long)]
+    pub port: Option<u16>,
+    /// Server mode, exposed at /get_appstate_mode
+    #[arg(long)]
+    pub mode: Option<String>,
+    /// Seconds to drain in-flight requests after SIGTERM
+    #[arg(long)]
+    pub shutdown_timeout: Option<u64>,
+}
+impl ServeArgs {
+    pub fn apply(self, server: &mut ServerConfig) {
+        if let Some(host) = self.host {
+            server.host = host;
+        }
+        if let Some(port) = self.port {
+            server.port = port;
+        }
+        if let Some(mode) = self.mode {
+            server.mode = mode;
+        }
+        if let Some(secs) = self.shutdown_timeout {
+            server.shutdown_timeout = Duration::from_secs(secs);
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MigrateCommand {
+    /// Apply pending migrations, all of them by default
+    Up {
+        #[arg(short = 'n', long)]
+        steps: Option<u32>,
+    },
+    /// Roll back applied migrations, the last one by default
+    Down {
+        #[arg(short = 'n', long, default_value_t = 1)]
+        steps: u32,
+    },
+    /// List migrations and whether they are applied
+    Status,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum UserCommand {
+    /// Create a user. The password is read from stdin when --password is not given
+    Create {
+        username: String,
+        #[arg(long)]
+        password: Option<String>,
+        /// admin, operator or user
+        #[arg(long, default_value_t = Role::User)]
+        role: Role,
+        #[arg(long)]
+        email: Option<String>,
+    },
+    /// Set a new password and log the user out everywhere
+    ResetPassword {
+        username: String,
+        #[arg(long)]
+        password: Option<String>,
+    },
+    /// Change the role, it takes effect when the user logs in or refreshes the next time
+    SetRole { username: String, role: Role },
+    /// Log the user out everywhere by revoking every session
+    RevokeSessions { username: String },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ChainCommand {
+    /// Show the ERC20 token balances of ETH_ADDR0 and ETH_ADDR1
+    Balance,
+    /// Compile the contracts folder and deploy it to a local ganache chain
+    Deploy,
+    /// Send ether from ETH_ADDR0 to ETH_ADDR1
+    Send {
+        /// Amount in ether
+        amount: f64,
+        /// Send the ERC20 token at ETH_ERC20TOKEN instead of ether
+        #[arg(long)]
+        erc20: bool,
+    },
+}
+
+pub async fn migrate(config: &Config, command: MigrateCommand) -> Result<()> {
+    let db_conn = connect(config).await?;
+    match command {
+        MigrateCommand::Up { steps } => {
+            Migrator::up(&db_conn, steps).await?;
+            println!("migrations applied");
+        }
+        MigrateCommand::Down { steps } => {
+            Migrator::down(&db_conn, Some(steps)).await?;
+            println!("migrations rolled back");
+        }
+        MigrateCommand::Status => {
+            Migrator::install(&db_conn).await?;
+            let applied: Vec<String> = Migrator::get_migration_models(&db_conn)
+                .await?
+                .into_iter()
+                .map(|model| model.version)
+                .collect();
+            for migration in Migrator::migrations() {
+                let name = migration.name();
+                let status = if applied.iter().any(|version| version == name) {
+                    "Applied"
+                } else {
+                    "Pending"
+                };
+                println!("{name} ... {status}");
+            }
+        }
+    }
+    Ok(())
+}
+
+pub async fn user(config: &Config, command: UserCommand) -> Result<()> {
+    let db_conn = connect(config).await?;
+    let users = SeaOrmUserRepository::new(db_conn.clone());
+    let sessions = SeaOrmSessionRepository::new(db_conn.clone());
+    let api_keys = SeaOrmApiKeyRepository::new(db_conn);
+    match command {
+        UserCommand::Create {
+            username,
+            password,
+            role,
+            email,
+        } => {
+            if let Err(err) = validate_username(&username) {
+                bail!("username {username} {err}");
+            }
+            let password = read_password(password)?;
+            let new_user = users
+                .create(NewUser {
+                    username,
+                    password: hash(password, config)?,
+                    role: role.to_string(),
+                    email: email.map(|email| email.trim().to_lowercase()),
+                })
+                .await
+                .wrap_err("saving new user failed")?;
+            println!("user {} created with id {}", new_user.username, new_user.id);
+        }
+        UserCommand::ResetPassword { username, password } => {
+            let password = read_password(password)?;
+            let mut user = find_user(&users, &username).await?;
+            user.password = hash(password, config)?;
+            let user = users.update(user).await?;
+            let revoked = sessions.revoke_all(user.id).await?;
+            let deleted = api_keys.delete_all(user.id).await?;
+            println!(
+                "password of {username} is reset, {revoked} sessions revoked, {deleted} API keys deleted"
+            );
+        }
+        UserCommand::SetRole { username, role } => {
+            let mut user = find_user(&users, &username).await?;
+            user.role = role.to_string();
+            users.update(user).await?;
+            println!("{username} is now {role}");
+        }
+        UserCommand::RevokeSessions { username } => {
+            let user = find_user(&users, &username).await?;
+            let revoked = sessions.revoke_all(user.id).await?;
+            println!("{revoked} sessions of {username} revoked");
+        }
+    }
+    Ok(())
+}
+
+pub async fn chain(config: &Config, command: ChainCommand) -> Result<()> {
+    let chain: &ChainConfig = config
+        .chain
+        .as_ref()
+        .ok_or_else(|| eyre!("blockchain is not configured"))?;
+    match command {
+        ChainCommand::Balance => {
+            let (bal0, bal1) = ethereum_live_read(chain).await?;
+            println!("{:?}: {bal0}", chain.addr0);
+            println!("{:?}: {bal1}", chain.addr1);
+        }
+        ChainCommand::Deploy => compile_deploy_contract(&chain.mnemonic).await?,
+        ChainCommand::Send { amount, erc20 } => {
+            let shutdown = CancellationToken::new();
+            let (txn_hash, balance) = if erc20 {
+                ethereum_live_write(chain, amount, &shutdown).await?
+            } else {
+                let to_addr = format!("{:?}", chain.addr1);
+                ethereum_send_ether(chain, to_addr, amount, &shutdown).await?
+            };
+            println!("txn_hash: {txn_hash}");
+            println!("balance: {balance}");
+        }
+    }
+    Ok(())
+}
+
+async fn connect(config: &Config) -> Result<DatabaseConnection> {
+    connect_db(&config.database)
+        .await
+        .wrap_err("failed to connect to database")
+}
+
+async fn find_user(users: &impl UserRepository, username: &str) -> Result<UserModel> {
+    users
+        .find_by_username(username)
+        .await?
+        .ok_or_else(|| eyre!("user {username} not found"))
+}
+
+fn hash(password: String, config: &Config) -> Result<String> {
+    hash_password(password, config.auth.hash_cost).map_err(|_e| eyre!("hashing password failed"))
+}
+
+// keeps passwords out of the shell history
+fn read_password(password: Option<String>) -> Result<String> {
+    let password = match password {
+        Some(password) => password,
+        None => {
+            print!("password: ");
+            io::stdout().flush()?;
+            let mut line = String::new();
+            io::stdin().lock().read_line(&mut line)?;
+            line.trim_end_matches(['\r', '\n']).to_owned()
+        }
+    };
+    if let Err(err) = validate_password(&password) {
+        bail!("password {err}");
+    }
+    Ok(password)
+}
@@ -0,0 +1,235 @@
+//! The one error type of the HTTP API. Every failure is rendered as an RFC 7807
+//! `application/problem+json` body whose `code` is stable, so clients can branch on it:
+//!
+//! ```json
+//! {"type":"/problems/not_found","title":"Not Found","status":404,"detail":"task 7 not found","code":"not_found"}
+//! ```
+use axum::{
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use sea_orm::{DbErr, RuntimeErr};
+use serde::Serialize;
+use serde_json::Value;
+use validator::ValidationErrors;
+
+#[derive(Debug)]
+pub enum AppError {
+    /// The request is malformed or breaks a rule. `errors` maps field names to messages
+    Validation {
+        detail: String,
+        errors: Option<Value>,
+    },
+    Unauthorized(String),
+    SessionExpired,
+    /// Logged in, but the role doesn't grant the permission
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    /// `If-Match` names another version of the resource than the stored one
+    PreconditionFailed(String),
+    /// The body is larger than the server accepts
+    PayloadTooLarge(String),
+    /// The body is not sent as `application/json`
+    UnsupportedMediaType(String),
+    /// Too many failed attempts, the client may try again after this many seconds
+    TooManyRequests(u64),
+    Database(DbErr),
+    /// The Ethereum node or a contract call failed, or the chain is not configured
+    Chain(String),
+    /// Some other remote service failed
+    Upstream(String),
+    Internal(String),
+}
+
+#[derive(Serialize, Debug)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: &'static str,
+    pub status: u16,
+    pub detail: String,
+    pub code: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errors: Option<Value>,
+}
+
+impl AppError {
+    pub fn validation(detail: impl Into<String>) -> Self {
+        Self::Validation {
+            detail: detail.into(),
+            errors: None,
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Validation { .. } => StatusCode::BAD_REQUEST,
+            Self::Unauthorized(_) | Self::SessionExpired => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            Self::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::Database(_) | Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Chain(_) | Self::Upstream(_) => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    /// Machine readable code, part of the API contract: never rename one
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Validation { .. } => "validation_failed",
+            Self::Unauthorized(_) => "unauthorized",
+            Self::SessionExpired => "session_expired",
+            Self::Forbidden(_) => "forbidden",
+            Self::NotFound(_) => "not_found",
+            Self::Conflict(_) => "conflict",
+            Self::PreconditionFailed(_) => "precondition_failed",
+            Self::PayloadTooLarge(_) => "payload_too_large",
+            Self::UnsupportedMediaType(_) => "unsupported_media_type",
+            Self::TooManyRequests(_) => "too_many_requests",
+            Self::Database(_) => "database_error",
+            Self::Chain(_) => "chain_error",
+            Self::Upstream(_) => "upstream_error",
+            Self::Internal(_) => "internal_error",
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let code = self.code();
+        let retry_after = match &self {
+            Self::TooManyRequests(secs) => Some(*secs),
+            _ => None,
+        };
+        let (detail, errors) = match self {
+            Self::Validation { detail, errors } => (detail, errors),
+            Self::SessionExpired => ("session expired, log in again".to_owned(), None),
+            Self::TooManyRequests(secs) => {
+                (format!("too many attempts, try again in {secs}s"), None)
+            }
+            // database and internal details can leak hosts or queries, they only go to the log
+            Self::Database(err) => {
+                println!("{code}: {err}");
+                ("database error".to_owned(), None)
+            }
+            Self::Internal(detail) => {
+                println!("{code}: {detail}");
+                ("internal error".to_owned(), None)
+            }
+            Self::Unauthorized(detail)
+            | Self::Forbidden(detail)
+            | Self::NotFound(detail)
+            | Self::Conflict(detail)
+            | Self::PreconditionFailed(detail)
+            | Self::PayloadTooLarge(detail)
+            | Self::UnsupportedMediaType(detail)
+            | Self::Chain(detail)
+            | Self::Upstream(detail) => (detail, None),
+        };
+        let problem = Problem {
+            problem_type: format!("/problems/{code}"),
+            title: status.canonical_reason().unwrap_or_default(),
+            status: status.as_u16(),
+            detail,
+            code,
+            errors,
+        };
+        let mut response = (
+            status,
+            [(header::CONTENT_TYPE, "application/problem+json")],
+            Json(problem),
+        )
+            .into_response();
+        if let Some(secs) = retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+        }
+        response
+    }
+}
+
+impl From<DbErr> for AppError {
+    fn from(err: DbErr) -> Self {
+        Self::Database(err)
+    }
+}
+
+/// A unique index rejected the row, e.g. a name taken by a concurrent request between the check
+/// and the insert
+pub fn is_unique_violation(err: &DbErr) -> bool {
+    let (DbErr::Exec(RuntimeErr::SqlxError(err)) | DbErr::Query(RuntimeErr::SqlxError(err))) = err
+    else {
+        return false;
+    };
+    let Some(err) = err.as_database_error() else {
+        return false;
+    };
+    // Postgres, SQLite unique and primary key. MySQL reports every integrity error as 23000
+    match err.code().as_deref() {
+        Some("23505" | "2067" | "1555") => true,
+        Some("23000") => err.message().starts_with("Duplicate entry"),
+        _ => false,
+    }
+}
+
+// a body that is no JSON or has the wrong shape for the handler is a validation error, a too
+// large body or a missing JSON content type keep their own status
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        match rejection.status() {
+            StatusCode::PAYLOAD_TOO_LARGE => Self::PayloadTooLarge(rejection.body_text()),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE => Self::UnsupportedMediaType(rejection.body_text()),
+            _ => Self::validation(rejection.body_text()),
+        }
+    }
+}
+
+// a query string that doesn't fit the handler
+impl From<QueryRejection> for AppError {
+    fn from(rejection: QueryRejection) -> Self {
+        Self::validation(rejection.body_text())
+    }
+}
+
+// a path segment that doesn't fit the handler, e.g. `/tasks/abc`. A route without the
+// parameters the handler asks for is a bug on our side
+impl From<PathRejection> for AppError {
+    fn from(rejection: PathRejection) -> Self {
+        match rejection.status() {
+            StatusCode::INTERNAL_SERVER_ERROR => Self::Internal(rejection.body_text()),
+            _ => Self::validation(rejection.body_text()),
+        }
+    }
+}
+
+impl From<ValidationErrors> for AppError {
+    fn from(errors: ValidationErrors) -> Self {
+        let fields = errors
+            .field_errors()
+            .into_iter()
+            .map(|(field, errors)| {
+                let messages: Vec<String> = errors
+                    .iter()
+                    .map(|error| match &error.message {
+                        Some(message) => message.to_string(),
+                        None => error.code.to_string(),
+                    })
+                    .collect();
+                (field.to_owned(), Value::from(messages))
+            })
+            .collect::<serde_json::Map<_, _>>();
+        Self::Validation {
+            detail: "some fields are invalid".to_owned(),
+            errors: Some(Value::Object(fields)),
+        }
+    }
+}
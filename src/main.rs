@@ -1,37 +1,142 @@
 //use sqlx::{MySql, MySqlPool};
-use dotenvy::dotenv;
-use std::net::SocketAddr;
+use axum_server_tls::Handle;
+use clap::Parser;
+use eyre::{Result, WrapErr};
+use std::{net::SocketAddr, sync::Arc};
+use tokio::time::Instant;
 pub mod blockchain;
+mod cli;
+use cli::{Cli, Command, ServeArgs};
+mod config;
+use config::Config;
 mod routes;
 use routes::create_routes;
 mod database;
 use database::*;
 mod entities; //load up all db entity files
+mod error;
+mod mailer;
+mod migration;
+mod rbac;
+mod repository;
+use migration::{Migrator, MigratorTrait};
+use repository::orm::SeaOrmTaskRepository;
+mod shutdown;
+use shutdown::Shutdown;
+mod throttle;
+mod tls;
+mod trash;
 mod utils;
+mod validation;
 
 #[tokio::main]
 async fn main() {
     // initialize tracing
     tracing_subscriber::fmt::init();
-    dotenv().expect(".env file not found");
-    println!(".env file is found");
-    let db_postgres_uri = dotenvy::var("DB_POSTGRES_URL").expect("postgres url not found");
-    //let db_postgres_uri = dotenv!("DB_POSTGRES_URL");
-    let db_conn = connect_db(db_postgres_uri.as_str())
+    let cli = Cli::parse();
+    let mut config = match Config::load(&cli.config) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    };
+    println!("config is valid");
+
+    let command = cli
+        .command
+        .unwrap_or_else(|| Command::Serve(ServeArgs::default()));
+    let result = match command {
+        Command::Serve(args) => {
+            args.apply(&mut config.server);
+            serve(config).await
+        }
+        Command::Migrate(command) => cli::migrate(&config, command).await,
+        Command::User(command) => cli::user(&config, command).await,
+        Command::Chain(command) => cli::chain(&config, command).await,
+    };
+    if let Err(err) = result {
+        eprintln!("{err:?}");
+        std::process::exit(1);
+    }
+}
+
+async fn serve(config: Config) -> Result<()> {
+    let mailer = mailer::from_config(&config.mail)?;
+    let db_conn = connect_db(&config.database)
         .await
-        .expect("failed to connect to database");
+        .wrap_err("failed to connect to database")?;
     println!("db_conn is successful");
-    let mode = "normal".to_owned();
-    let app = create_routes(mode, db_conn);
+    Migrator::up(&db_conn, None)
+        .await
+        .wrap_err("failed to run database migrations")?;
+    println!("db migrations are up to date");
+    let addr = SocketAddr::new(config.server.host, config.server.port);
+    let server_config = config.server.clone();
+    let shutdown = Shutdown::new();
+    let handle = Handle::new();
+    trash::spawn_purger(
+        Arc::new(SeaOrmTaskRepository::new(db_conn.clone())),
+        config.trash.clone(),
+        &shutdown,
+    );
+    let app = create_routes(Arc::new(config), db_conn.clone(), mailer, shutdown.clone());
 
-    // `axum::Server` is a re-export of `hyper::Server`
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3000)); // 0.0.0.0 is compatible for docker containers and VM
-    tracing::debug!("listening on {}", addr);
-    println!("Server running on localhost:3000");
-    axum::Server::bind(&addr)
-        .serve(app.await.into_make_service())
+    let rustls = match &server_config.tls {
+        Some(tls_config) => {
+            let rustls = tls::load(tls_config).await?;
+            tls::watch(rustls.clone(), tls_config.clone(), &shutdown);
+            if let Some(http_port) = tls_config.redirect_http_port {
+                let http_addr = SocketAddr::new(addr.ip(), http_port);
+                tls::redirect_http(http_addr, addr.port(), handle.clone(), &shutdown);
+            }
+            Some(rustls)
+        }
+        None => None,
+    };
+    // the peer address is recorded as the ip of new sessions
+    let service = app
         .await
-        .unwrap();
+        .into_make_service_with_connect_info::<SocketAddr>();
+    tracing::debug!("listening on {}", addr);
+    let server = async {
+        match rustls {
+            Some(rustls) => {
+                println!("Server running on https://{addr}");
+                axum_server_tls::bind_rustls(addr, rustls)
+                    .handle(handle.clone())
+                    .serve(service)
+                    .await
+            }
+            None => {
+                println!("Server running on http://{addr}");
+                axum_server_tls::bind(addr)
+                    .handle(handle.clone())
+                    .serve(service)
+                    .await
+            }
+        }
+    };
+    tokio::pin!(server);
+
+    let drained = tokio::select! {
+        result = &mut server => {
+            result?;
+            true
+        }
+        _ = shutdown.triggered() => false,
+    };
+    let deadline = Instant::now() + server_config.shutdown_timeout;
+    shutdown.drain_until(deadline);
+    if !drained {
+        // stop accepting connections, in-flight requests get until the deadline
+        handle.graceful_shutdown(Some(server_config.shutdown_timeout));
+        server.await?;
+    }
+    shutdown.wait_for_tasks(deadline).await;
+    db_conn.close().await?;
+    println!("shutdown: complete");
+    Ok(())
 }
 
 //CORS https://docs.rs/tower-http/latest/tower_http/cors/index.html
@@ -0,0 +1,61 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20230201_000001_create_users_table::Users;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Tasks::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Tasks::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Tasks::Priority).string_len(4))
+                    .col(ColumnDef::new(Tasks::Title).string_len(255).not_null())
+                    .col(ColumnDef::new(Tasks::CompletedAt).timestamp_with_time_zone())
+                    .col(ColumnDef::new(Tasks::Description).text())
+                    .col(ColumnDef::new(Tasks::DeletedAt).timestamp_with_time_zone())
+                    .col(ColumnDef::new(Tasks::UserId).integer())
+                    .col(ColumnDef::new(Tasks::IsDefault).boolean().default(false))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_tasks_user_id")
+                            .from(Tasks::Table, Tasks::UserId)
+                            .to(Users::Table, Users::Id),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Tasks::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum Tasks {
+    Table,
+    Id,
+    Priority,
+    Title,
+    CompletedAt,
+    Description,
+    DeletedAt,
+    UserId,
+    IsDefault,
+    CreatedAt,
+    Version,
+}
@@ -0,0 +1,33 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20230201_000002_create_tasks_table::Tasks;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// nullable: SQLite can't add a column defaulting to the current time, and older tasks have no
+// creation time to fill in
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Tasks::Table)
+                    .add_column(ColumnDef::new(Tasks::CreatedAt).timestamp_with_time_zone())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Tasks::Table)
+                    .drop_column(Tasks::CreatedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
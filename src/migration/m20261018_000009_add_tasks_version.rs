@@ -0,0 +1,37 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20230201_000002_create_tasks_table::Tasks;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// bumped on every update, the ETag of a task
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Tasks::Table)
+                    .add_column(
+                        ColumnDef::new(Tasks::Version)
+                            .integer()
+                            .not_null()
+                            .default(1),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Tasks::Table)
+                    .drop_column(Tasks::Version)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,41 @@
+//! Embedded schema migrations. The entity files in `src/entities` mirror these tables,
+//! regenerate them with sea-orm-cli (see README) whenever a migration changes the schema.
+//!
+//! Migrations run on every backend in `database`, so stick to the schema builder and avoid raw SQL.
+//! SQLite can't add foreign keys or alter columns of an existing table: declare foreign keys in
+//! `create_table` and add one column per `alter_table`. MySQL can't index `text`, so unique or
+//! indexed columns need `string_len`.
+pub use sea_orm_migration::prelude::*;
+
+mod m20230201_000001_create_users_table;
+mod m20230201_000002_create_tasks_table;
+mod m20261018_000001_create_refresh_tokens_table;
+mod m20261018_000002_create_sessions_table;
+mod m20261018_000003_drop_users_token;
+mod m20261018_000004_add_users_role;
+mod m20261018_000005_add_users_email;
+mod m20261018_000006_create_user_tokens_table;
+mod m20261018_000007_create_api_keys_table;
+mod m20261018_000008_add_tasks_created_at;
+mod m20261018_000009_add_tasks_version;
+
+pub struct Migrator;
+
+#[async_trait::async_trait]
+impl MigratorTrait for Migrator {
+    fn migrations() -> Vec<Box<dyn MigrationTrait>> {
+        vec![
+            Box::new(m20230201_000001_create_users_table::Migration),
+            Box::new(m20230201_000002_create_tasks_table::Migration),
+            Box::new(m20261018_000001_create_refresh_tokens_table::Migration),
+            Box::new(m20261018_000002_create_sessions_table::Migration),
+            Box::new(m20261018_000003_drop_users_token::Migration),
+            Box::new(m20261018_000004_add_users_role::Migration),
+            Box::new(m20261018_000005_add_users_email::Migration),
+            Box::new(m20261018_000006_create_user_tokens_table::Migration),
+            Box::new(m20261018_000007_create_api_keys_table::Migration),
+            Box::new(m20261018_000008_add_tasks_created_at::Migration),
+            Box::new(m20261018_000009_add_tasks_version::Migration),
+        ]
+    }
+}
@@ -0,0 +1,255 @@
+//! Role based access control. Every user has one role, it is written into the `roles` claim of
+//! the JWT and a handler declares what it needs with a `Require<..>` argument:
+//!
+//! ```ignore
+//! pub async fn eth_send_ether(_: Require<EthWrite>, ...) -> Result<..., AppError>
+//! ```
+//!
+//! The handler has to sit behind the `auth` route layer, which puts the claims into the request.
+//! API keys carry scopes, a list of permissions: a request with a key is granted what both the
+//! role and the scopes of the key grant.
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+use std::{fmt, marker::PhantomData, str::FromStr};
+
+use crate::{error::AppError, utils::Claims};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Admin,
+    Operator,
+    User,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Admin => "admin",
+            Self::Operator => "operator",
+            Self::User => "user",
+        }
+    }
+
+    pub fn has(&self, permission: Permission) -> bool {
+        match self {
+            Self::Admin => true,
+            Self::Operator => matches!(
+                permission,
+                Permission::TasksRead | Permission::TasksWrite | Permission::EthWrite
+            ),
+            Self::User => matches!(permission, Permission::TasksRead | Permission::TasksWrite),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "admin" => Ok(Self::Admin),
+            "operator" => Ok(Self::Operator),
+            "user" => Ok(Self::User),
+            _ => Err(format!("unknown role `{s}`, use admin, operator or user")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    /// List and read the own tasks
+    TasksRead,
+    /// Create, change and delete the own tasks
+    TasksWrite,
+    /// Read and change the tasks of every user
+    TasksAdmin,
+    /// Send transactions and deploy contracts with the server wallet
+    EthWrite,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 4] = [
+        Self::TasksRead,
+        Self::TasksWrite,
+        Self::TasksAdmin,
+        Self::EthWrite,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::TasksRead => "tasks:read",
+            Self::TasksWrite => "tasks:write",
+            Self::TasksAdmin => "tasks:admin",
+            Self::EthWrite => "eth:write",
+        }
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Permission {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|permission| permission.as_str() == s)
+            .ok_or_else(|| format!("unknown permission `{s}`"))
+    }
+}
+
+/// Whether one of the roles in the token grants the permission, and the scopes too for an API
+/// key. Unknown roles and scopes grant nothing
+pub fn granted(claims: &Claims, permission: Permission) -> bool {
+    let by_role = claims
+        .roles
+        .iter()
+        .filter_map(|role| role.parse::<Role>().ok())
+        .any(|role| role.has(permission));
+    let by_scope = claims
+        .scopes
+        .as_ref()
+        .is_none_or(|scopes| scopes.iter().any(|scope| scope == permission.as_str()));
+    by_role && by_scope
+}
+
+/// Marker types for `Require`, one per permission
+pub trait RequiredPermission {
+    const PERMISSION: Permission;
+}
+pub struct TasksRead;
+impl RequiredPermission for TasksRead {
+    const PERMISSION: Permission = Permission::TasksRead;
+}
+pub struct TasksWrite;
+impl RequiredPermission for TasksWrite {
+    const PERMISSION: Permission = Permission::TasksWrite;
+}
+pub struct EthWrite;
+impl RequiredPermission for EthWrite {
+    const PERMISSION: Permission = Permission::EthWrite;
+}
+
+/// Extractor that rejects the request with 403 unless one of the roles in the token grants `P`
+pub struct Require<P>(PhantomData<P>);
+
+#[async_trait]
+impl<S, P> FromRequestParts<S> for Require<P>
+where
+    S: Send + Sync,
+    P: RequiredPermission,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(claims) = parts.extensions.get::<Claims>() else {
+            return Err(AppError::Unauthorized(
+                "missing bearer token or API key".to_owned(),
+            ));
+        };
+        if !granted(claims, P::PERMISSION) {
+            return Err(AppError::Forbidden(format!(
+                "{} permission required",
+                P::PERMISSION.as_str()
+            )));
+        }
+        Ok(Self(PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Request;
+
+    use super::*;
+
+    fn claims(role: &str, scopes: Option<&[Permission]>) -> Claims {
+        Claims {
+            sub: "1".to_owned(),
+            iss: String::new(),
+            aud: String::new(),
+            jti: String::new(),
+            sid: String::new(),
+            roles: vec![role.to_owned()],
+            scopes: scopes.map(|scopes| scopes.iter().map(|p| p.as_str().to_owned()).collect()),
+            exp: 0,
+            iat: 0,
+        }
+    }
+
+    fn granted_of(claims: &Claims) -> Vec<Permission> {
+        Permission::ALL
+            .into_iter()
+            .filter(|&permission| granted(claims, permission))
+            .collect()
+    }
+
+    async fn require<P: RequiredPermission>(claims: Option<Claims>) -> Result<(), AppError> {
+        let (mut parts, _) = Request::new(()).into_parts();
+        if let Some(claims) = claims {
+            parts.extensions.insert(claims);
+        }
+        Require::<P>::from_request_parts(&mut parts, &())
+            .await
+            .map(|_| ())
+    }
+
+    #[test]
+    fn roles_grant_their_permissions() {
+        assert_eq!(granted_of(&claims("admin", None)), Permission::ALL);
+        assert_eq!(
+            granted_of(&claims("operator", None)),
+            [
+                Permission::TasksRead,
+                Permission::TasksWrite,
+                Permission::EthWrite
+            ]
+        );
+        assert_eq!(
+            granted_of(&claims("user", None)),
+            [Permission::TasksRead, Permission::TasksWrite]
+        );
+        assert_eq!(granted_of(&claims("root", None)), []);
+    }
+
+    #[test]
+    fn api_key_scopes_narrow_the_role() {
+        let key = claims("admin", Some(&[Permission::TasksRead]));
+        assert_eq!(granted_of(&key), [Permission::TasksRead]);
+        // a scope the role lacks grants nothing
+        let key = claims(
+            "user",
+            Some(&[
+                Permission::TasksRead,
+                Permission::TasksAdmin,
+                Permission::EthWrite,
+            ]),
+        );
+        assert_eq!(granted_of(&key), [Permission::TasksRead]);
+        assert_eq!(granted_of(&claims("admin", Some(&[]))), []);
+    }
+
+    #[tokio::test]
+    async fn require_checks_the_claims() {
+        assert!(require::<TasksWrite>(Some(claims("user", None)))
+            .await
+            .is_ok());
+        let denied = require::<EthWrite>(Some(claims("user", None))).await;
+        assert!(matches!(denied, Err(AppError::Forbidden(_))));
+        let key = claims("operator", Some(&[Permission::TasksRead]));
+        assert!(require::<TasksRead>(Some(key.clone())).await.is_ok());
+        let denied = require::<TasksWrite>(Some(key)).await;
+        assert!(matches!(denied, Err(AppError::Forbidden(_))));
+        let anonymous = require::<TasksRead>(None).await;
+        assert!(matches!(anonymous, Err(AppError::Unauthorized(_))));
+    }
+}
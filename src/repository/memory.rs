@@ -0,0 +1,606 @@
+//! Repositories that keep their rows in maps, for handler tests without a database. They behave
+//! like the orm ones, including the version check of task updates and the NULL order of sorts.
+use axum::async_trait;
+use chrono::Utc;
+use sea_orm::{prelude::DateTimeWithTimeZone, DbErr};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BTreeMap,
+    sync::Mutex,
+};
+
+use super::{
+    ApiKeyRepository, NewApiKey, NewRefreshToken, NewSession, NewTask, NewUser, NewUserToken,
+    RefreshTokenRepository, SessionRepository, SortValue, TaskFilter, TaskPage, TaskRepository,
+    TaskSearch, TaskSort, UserRepository, UserTokenKind, UserTokenRepository,
+};
+use crate::entities::{api_keys, refresh_tokens, sessions, tasks, user_tokens, users};
+
+// rows by id, plus the last id handed out like an auto increment column
+struct Table<T> {
+    rows: BTreeMap<i32, T>,
+    last_id: i32,
+}
+impl<T> Default for Table<T> {
+    fn default() -> Self {
+        Self {
+            rows: BTreeMap::new(),
+            last_id: 0,
+        }
+    }
+}
+impl<T> Table<T> {
+    fn next_id(&mut self) -> i32 {
+        self.last_id += 1;
+        self.last_id
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryUserRepository {
+    table: Mutex<Table<users::Model>>,
+}
+impl MemoryUserRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl UserRepository for MemoryUserRepository {
+    async fn create(&self, user: NewUser) -> Result<users::Model, DbErr> {
+        let mut table = self.table.lock().unwrap();
+        if table.rows.values().any(|row| row.username == user.username) {
+            return Err(DbErr::Custom(format!(
+                "username {} already exists",
+                user.username
+            )));
+        }
+        if user.email.is_some() && table.rows.values().any(|row| row.email == user.email) {
+            return Err(DbErr::Custom("email already exists".to_owned()));
+        }
+        let id = table.next_id();
+        let model = users::Model {
+            id,
+            username: user.username,
+            password: user.password,
+            deleted_at: None,
+            role: user.role,
+            email: user.email,
+            email_verified_at: None,
+        };
+        table.rows.insert(id, model.clone());
+        Ok(model)
+    }
+
+    async fn find_by_id(&self, id: i32) -> Result<Option<users::Model>, DbErr> {
+        Ok(self.table.lock().unwrap().rows.get(&id).cloned())
+    }
+
+    async fn find_by_username(&self, username: &str) -> Result<Option<users::Model>, DbErr> {
+        let table = self.table.lock().unwrap();
+        Ok(table
+            .rows
+            .values()
+            .find(|row| row.username == username)
+            .cloned())
+    }
+
+    async fn find_by_email(&self, email: &str) -> Result<Option<users::Model>, DbErr> {
+        let table = self.table.lock().unwrap();
+        Ok(table
+            .rows
+            .values()
+            .find(|row| row.email.as_deref() == Some(email))
+            .cloned())
+    }
+
+    async fn update(&self, user: users::Model) -> Result<users::Model, DbErr> {
+        let mut table = self.table.lock().unwrap();
+        match table.rows.get_mut(&user.id) {
+            Some(row) => {
+                *row = user.clone();
+                Ok(user)
+            }
+            None => Err(DbErr::RecordNotUpdated),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryTaskRepository {
+    table: Mutex<Table<tasks::Model>>,
+}
+impl MemoryTaskRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl TaskRepository for MemoryTaskRepository {
+    async fn create(&self, task: NewTask) -> Result<tasks::Model, DbErr> {
+        let mut table = self.table.lock().unwrap();
+        let id = table.next_id();
+        let model = tasks::Model {
+            id,
+            priority: task.priority,
+            title: task.title,
+            completed_at: None,
+            description: task.description,
+            deleted_at: None,
+            user_id: task.user_id,
+            is_default: Some(false),
+            created_at: Some(Utc::now().into()),
+            version: 1,
+        };
+        table.rows.insert(id, model.clone());
+        Ok(model)
+    }
+
+    async fn find_by_id(&self, id: i32) -> Result<Option<tasks::Model>, DbErr> {
+        Ok(self.table.lock().unwrap().rows.get(&id).cloned())
+    }
+
+    async fn list(&self, filter: TaskFilter, page: TaskPage) -> Result<Vec<tasks::Model>, DbErr> {
+        let table = self.table.lock().unwrap();
+        let mut tasks: Vec<_> = table
+            .rows
+            .values()
+            .filter(|task| matches(task, &filter))
+            .cloned()
+            .collect();
+        let sort_keys = |task: &tasks::Model| -> Vec<SortValue> {
+            page.sort
+                .iter()
+                .map(|sort| sort.field.value_of(task))
+                .collect()
+        };
+        tasks.sort_by(|a, b| compare_keys(&page.sort, &sort_keys(a), &sort_keys(b)));
+        Ok(tasks
+            .into_iter()
+            .filter(|task| match &page.after {
+                Some(after) => compare_keys(&page.sort, &sort_keys(task), after).is_gt(),
+                None => true,
+            })
+            .skip(page.offset as usize)
+            .take(page.limit as usize)
+            .collect())
+    }
+
+    async fn count(&self, filter: TaskFilter) -> Result<u64, DbErr> {
+        let table = self.table.lock().unwrap();
+        Ok(table
+            .rows
+            .values()
+            .filter(|task| matches(task, &filter))
+            .count() as u64)
+    }
+
+    async fn update(&self, task: tasks::Model) -> Result<tasks::Model, DbErr> {
+        let mut table = self.table.lock().unwrap();
+        match table.rows.get_mut(&task.id) {
+            Some(row) if row.version == task.version => {
+                *row = tasks::Model {
+                    version: task.version + 1,
+                    ..task
+                };
+                Ok(row.clone())
+            }
+            _ => Err(DbErr::RecordNotUpdated),
+        }
+    }
+
+    async fn delete_versioned(&self, id: i32, version: i32) -> Result<(), DbErr> {
+        let mut table = self.table.lock().unwrap();
+        match table.rows.get(&id) {
+            Some(row) if row.version == version => {
+                table.rows.remove(&id);
+                Ok(())
+            }
+            _ => Err(DbErr::RecordNotUpdated),
+        }
+    }
+
+    async fn purge_deleted(&self, before: DateTimeWithTimeZone) -> Result<u64, DbErr> {
+        let mut table = self.table.lock().unwrap();
+        let count = table.rows.len();
+        table.rows.retain(|_, task| {
+            task.deleted_at
+                .is_none_or(|deleted_at| deleted_at >= before)
+        });
+        Ok((count - table.rows.len()) as u64)
+    }
+}
+
+fn matches(task: &tasks::Model, filter: &TaskFilter) -> bool {
+    let in_range = |value: Option<DateTimeWithTimeZone>,
+                    from: Option<DateTimeWithTimeZone>,
+                    to: Option<DateTimeWithTimeZone>| {
+        (from.is_none() || value.is_some_and(|value| Some(value) >= from))
+            && (to.is_none() || value.is_some_and(|value| Some(value) < to))
+    };
+    task.deleted_at.is_some() == filter.deleted
+        && (filter.user_id.is_none() || task.user_id == filter.user_id)
+        && filter.ids.as_ref().is_none_or(|ids| ids.contains(&task.id))
+        && filter
+            .priorities
+            .as_ref()
+            .is_none_or(|priorities| priorities.contains(&task.priority))
+        && match &filter.title {
+            Some(title) => title.as_ref() == Some(&task.title),
+            None => true,
+        }
+        && filter
+            .completed
+            .is_none_or(|completed| task.completed_at.is_some() == completed)
+        && filter
+            .is_default
+            .is_none_or(|is_default| task.is_default.unwrap_or(false) == is_default)
+        && in_range(task.created_at, filter.created_from, filter.created_to)
+        && in_range(
+            task.completed_at,
+            filter.completed_from,
+            filter.completed_to,
+        )
+        && filter.search.as_ref().is_none_or(|search| {
+            // no full text search here
+            let (TaskSearch::Substring(text) | TaskSearch::FullText(text)) = search;
+            let text = text.to_lowercase();
+            task.title.to_lowercase().contains(&text)
+                || task
+                    .description
+                    .as_ref()
+                    .is_some_and(|description| description.to_lowercase().contains(&text))
+        })
+}
+
+// the order of two rows by their sort key values
+fn compare_keys(sort: &[TaskSort], a: &[SortValue], b: &[SortValue]) -> Ordering {
+    sort.iter()
+        .zip(a.iter().zip(b))
+        .map(|(sort, (a, b))| {
+            let ordering = compare_values(a, b);
+            if sort.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        })
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+// ascending order of two values of the same column, NULL after every other value like the orm
+// repository sorts
+fn compare_values(a: &SortValue, b: &SortValue) -> Ordering {
+    fn nulls_last<T: Ord>(a: &Option<T>, b: &Option<T>) -> Ordering {
+        match (a, b) {
+            (Some(a), Some(b)) => a.cmp(b),
+            (None, None) => Ordering::Equal,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(_), None) => Ordering::Less,
+        }
+    }
+    match (a, b) {
+        (SortValue::Int(a), SortValue::Int(b)) => a.cmp(b),
+        (SortValue::Text(a), SortValue::Text(b)) => nulls_last(a, b),
+        (SortValue::Time(a), SortValue::Time(b)) => nulls_last(a, b),
+        // values of different columns are never compared
+        _ => Ordering::Equal,
+    }
+}
+
+// sessions are keyed by their uuid, so they don't fit `Table`
+#[derive(Default)]
+pub struct MemorySessionRepository {
+    rows: Mutex<BTreeMap<String, sessions::Model>>,
+}
+impl MemorySessionRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl SessionRepository for MemorySessionRepository {
+    async fn create(&self, session: NewSession) -> Result<sessions::Model, DbErr> {
+        let mut rows = self.rows.lock().unwrap();
+        if rows.contains_key(&session.id) {
+            return Err(DbErr::Custom(format!(
+                "session {} already exists",
+                session.id
+            )));
+        }
+        let now = Utc::now().into();
+        let model = sessions::Model {
+            id: session.id,
+            user_id: session.user_id,
+            user_agent: session.user_agent,
+            ip: session.ip,
+            created_at: now,
+            last_seen_at: now,
+            expires_at: session.expires_at,
+            revoked_at: None,
+        };
+        rows.insert(model.id.clone(), model.clone());
+        Ok(model)
+    }
+
+    async fn find_by_id(&self, id: &str) -> Result<Option<sessions::Model>, DbErr> {
+        Ok(self.rows.lock().unwrap().get(id).cloned())
+    }
+
+    async fn list_active(&self, user_id: i32) -> Result<Vec<sessions::Model>, DbErr> {
+        let now = Utc::now();
+        let mut active: Vec<_> = self
+            .rows
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|row| row.user_id == user_id)
+            .filter(|row| row.revoked_at.is_none() && row.expires_at > now)
+            .cloned()
+            .collect();
+        active.sort_by_key(|row| Reverse(row.last_seen_at));
+        Ok(active)
+    }
+
+    async fn touch(&self, id: &str, last_seen_at: DateTimeWithTimeZone) -> Result<bool, DbErr> {
+        let mut rows = self.rows.lock().unwrap();
+        match rows.get_mut(id) {
+            Some(row) if row.revoked_at.is_none() => {
+                row.last_seen_at = last_seen_at;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn extend(
+        &self,
+        id: &str,
+        last_seen_at: DateTimeWithTimeZone,
+        expires_at: DateTimeWithTimeZone,
+    ) -> Result<bool, DbErr> {
+        let mut rows = self.rows.lock().unwrap();
+        match rows.get_mut(id) {
+            Some(row) if row.revoked_at.is_none() => {
+                row.last_seen_at = last_seen_at;
+                row.expires_at = expires_at;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn revoke(&self, id: &str) -> Result<bool, DbErr> {
+        let mut rows = self.rows.lock().unwrap();
+        match rows.get_mut(id) {
+            Some(row) if row.revoked_at.is_none() => {
+                row.revoked_at = Some(Utc::now().into());
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn revoke_all(&self, user_id: i32) -> Result<u64, DbErr> {
+        let mut count = 0;
+        for row in self.rows.lock().unwrap().values_mut() {
+            if row.user_id == user_id && row.revoked_at.is_none() {
+                row.revoked_at = Some(Utc::now().into());
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryRefreshTokenRepository {
+    table: Mutex<Table<refresh_tokens::Model>>,
+}
+impl MemoryRefreshTokenRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl RefreshTokenRepository for MemoryRefreshTokenRepository {
+    async fn create(&self, token: NewRefreshToken) -> Result<refresh_tokens::Model, DbErr> {
+        let mut table = self.table.lock().unwrap();
+        let id = table.next_id();
+        let model = refresh_tokens::Model {
+            id,
+            user_id: token.user_id,
+            family_id: token.family_id,
+            token_hash: token.token_hash,
+            created_at: Utc::now().into(),
+            expires_at: token.expires_at,
+            used_at: None,
+            revoked_at: None,
+        };
+        table.rows.insert(id, model.clone());
+        Ok(model)
+    }
+
+    async fn find_by_hash(&self, token_hash: &str) -> Result<Option<refresh_tokens::Model>, DbErr> {
+        let table = self.table.lock().unwrap();
+        Ok(table
+            .rows
+            .values()
+            .find(|row| row.token_hash == token_hash)
+            .cloned())
+    }
+
+    async fn mark_used(&self, id: i32) -> Result<bool, DbErr> {
+        let mut table = self.table.lock().unwrap();
+        match table.rows.get_mut(&id) {
+            Some(row) if row.used_at.is_none() && row.revoked_at.is_none() => {
+                row.used_at = Some(Utc::now().into());
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn revoke_family(&self, family_id: &str) -> Result<(), DbErr> {
+        let mut table = self.table.lock().unwrap();
+        for row in table.rows.values_mut() {
+            if row.family_id == family_id && row.revoked_at.is_none() {
+                row.revoked_at = Some(Utc::now().into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryUserTokenRepository {
+    table: Mutex<Table<user_tokens::Model>>,
+}
+impl MemoryUserTokenRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl UserTokenRepository for MemoryUserTokenRepository {
+    async fn create(&self, token: NewUserToken) -> Result<user_tokens::Model, DbErr> {
+        let mut table = self.table.lock().unwrap();
+        let id = table.next_id();
+        let model = user_tokens::Model {
+            id,
+            user_id: token.user_id,
+            kind: token.kind.as_str().to_owned(),
+            token_hash: token.token_hash,
+            email: token.email,
+            created_at: Utc::now().into(),
+            expires_at: token.expires_at,
+            used_at: None,
+        };
+        table.rows.insert(id, model.clone());
+        Ok(model)
+    }
+
+    async fn find_by_hash(
+        &self,
+        kind: UserTokenKind,
+        token_hash: &str,
+    ) -> Result<Option<user_tokens::Model>, DbErr> {
+        let table = self.table.lock().unwrap();
+        Ok(table
+            .rows
+            .values()
+            .find(|row| row.kind == kind.as_str() && row.token_hash == token_hash)
+            .cloned())
+    }
+
+    async fn mark_used(&self, id: i32) -> Result<bool, DbErr> {
+        let mut table = self.table.lock().unwrap();
+        match table.rows.get_mut(&id) {
+            Some(row) if row.used_at.is_none() => {
+                row.used_at = Some(Utc::now().into());
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn use_all(&self, user_id: i32, kind: UserTokenKind) -> Result<(), DbErr> {
+        let mut table = self.table.lock().unwrap();
+        let now = Utc::now();
+        for row in table.rows.values_mut() {
+            if row.user_id == user_id && row.kind == kind.as_str() && row.used_at.is_none() {
+                row.used_at = Some(now.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryApiKeyRepository {
+    table: Mutex<Table<api_keys::Model>>,
+}
+impl MemoryApiKeyRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl ApiKeyRepository for MemoryApiKeyRepository {
+    async fn create(&self, key: NewApiKey) -> Result<api_keys::Model, DbErr> {
+        let mut table = self.table.lock().unwrap();
+        if table.rows.values().any(|row| row.prefix == key.prefix) {
+            return Err(DbErr::Custom(format!(
+                "api key prefix {} already exists",
+                key.prefix
+            )));
+        }
+        let id = table.next_id();
+        let model = api_keys::Model {
+            id,
+            user_id: key.user_id,
+            name: key.name,
+            prefix: key.prefix,
+            key_hash: key.key_hash,
+            scopes: key.scopes,
+            created_at: Utc::now().into(),
+            expires_at: key.expires_at,
+            last_used_at: None,
+        };
+        table.rows.insert(id, model.clone());
+        Ok(model)
+    }
+
+    async fn find_by_id(&self, id: i32) -> Result<Option<api_keys::Model>, DbErr> {
+        Ok(self.table.lock().unwrap().rows.get(&id).cloned())
+    }
+
+    async fn find_by_prefix(&self, prefix: &str) -> Result<Option<api_keys::Model>, DbErr> {
+        let table = self.table.lock().unwrap();
+        Ok(table
+            .rows
+            .values()
+            .find(|row| row.prefix == prefix)
+            .cloned())
+    }
+
+    async fn list(&self, user_id: i32) -> Result<Vec<api_keys::Model>, DbErr> {
+        let table = self.table.lock().unwrap();
+        Ok(table
+            .rows
+            .values()
+            .rev()
+            .filter(|row| row.user_id == user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn update(&self, key: api_keys::Model) -> Result<api_keys::Model, DbErr> {
+        let mut table = self.table.lock().unwrap();
+        match table.rows.get_mut(&key.id) {
+            Some(row) => {
+                *row = key.clone();
+                Ok(key)
+            }
+            None => Err(DbErr::RecordNotUpdated),
+        }
+    }
+
+    async fn delete(&self, id: i32) -> Result<bool, DbErr> {
+        Ok(self.table.lock().unwrap().rows.remove(&id).is_some())
+    }
+
+    async fn delete_all(&self, user_id: i32) -> Result<u64, DbErr> {
+        let mut table = self.table.lock().unwrap();
+        let before = table.rows.len();
+        table.rows.retain(|_, row| row.user_id != user_id);
+        Ok((before - table.rows.len()) as u64)
+    }
+}
@@ -0,0 +1,307 @@
+//! Persistence of users, tasks, sessions and tokens behind traits, so handlers don't build sea-orm queries inline.
+//! `orm` talks to the configured database, `memory` keeps everything in maps for handler tests.
+use axum::async_trait;
+use sea_orm::{prelude::DateTimeWithTimeZone, DbErr};
+use std::{fmt, str::FromStr, sync::Arc};
+
+use crate::entities::{api_keys, refresh_tokens, sessions, tasks, user_tokens, users};
+
+#[cfg(test)]
+pub mod memory;
+pub mod orm;
+
+#[derive(Debug, Clone)]
+pub struct NewUser {
+    pub username: String,
+    /// bcrypt hash, never the plain password
+    pub password: String,
+    /// One of `rbac::Role`
+    pub role: String,
+    /// Lowercase, unverified
+    pub email: Option<String>,
+}
+
+/// What a `user_tokens` row is good for, stored in `kind`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserTokenKind {
+    VerifyEmail,
+    ResetPassword,
+}
+impl UserTokenKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::VerifyEmail => "verify_email",
+            Self::ResetPassword => "reset_password",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NewUserToken {
+    pub user_id: i32,
+    pub kind: UserTokenKind,
+    /// sha256 of the token, like refresh tokens
+    pub token_hash: String,
+    /// The address the token was mailed to
+    pub email: Option<String>,
+    pub expires_at: DateTimeWithTimeZone,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewTask {
+    pub title: String,
+    pub priority: Option<String>,
+    pub description: Option<String>,
+    pub user_id: Option<i32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewSession {
+    /// A random uuid, also put into the JWTs of the session as `sid`
+    pub id: String,
+    pub user_id: i32,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub expires_at: DateTimeWithTimeZone,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewRefreshToken {
+    pub user_id: i32,
+    /// Every token rotated out of the same login shares the family, it is the session id
+    pub family_id: String,
+    /// sha256 of the token, the token itself is only ever sent to the client
+    pub token_hash: String,
+    pub expires_at: DateTimeWithTimeZone,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewApiKey {
+    pub user_id: i32,
+    pub name: String,
+    /// Random and public, the key is looked up by it
+    pub prefix: String,
+    /// sha256 of the whole key
+    pub key_hash: String,
+    /// Permission names separated by spaces, see `rbac::Permission`
+    pub scopes: String,
+    /// Never expires when None
+    pub expires_at: Option<DateTimeWithTimeZone>,
+}
+
+/// Filters for `TaskRepository::list`, a task has to match all of them. `Some(None)` matches a
+/// NULL column, ranges include `from` and exclude `to`
+#[derive(Debug, Clone, Default)]
+pub struct TaskFilter {
+    /// Only tasks of this user, all tasks when None
+    pub user_id: Option<i32>,
+    /// The soft deleted tasks in the trash instead of the others
+    pub deleted: bool,
+    /// Any of these ids
+    pub ids: Option<Vec<i32>>,
+    /// Any of these priorities, None in the list for NULL
+    pub priorities: Option<Vec<Option<String>>>,
+    pub title: Option<Option<String>>,
+    /// Whether `completed_at` is set
+    pub completed: Option<bool>,
+    /// NULL counts as false
+    pub is_default: Option<bool>,
+    pub created_from: Option<DateTimeWithTimeZone>,
+    pub created_to: Option<DateTimeWithTimeZone>,
+    pub completed_from: Option<DateTimeWithTimeZone>,
+    pub completed_to: Option<DateTimeWithTimeZone>,
+    pub search: Option<TaskSearch>,
+}
+
+/// Text search over `title` and `description`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskSearch {
+    /// Case insensitive substring
+    Substring(String),
+    /// Postgres full text search with `websearch_to_tsquery` syntax, stemmed as english.
+    /// Other backends fall back to `Substring`
+    FullText(String),
+}
+
+/// Columns tasks can be sorted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskSortField {
+    Id,
+    Title,
+    Priority,
+    CreatedAt,
+    CompletedAt,
+    DeletedAt,
+}
+impl TaskSortField {
+    pub const ALL: [TaskSortField; 6] = [
+        Self::Id,
+        Self::Title,
+        Self::Priority,
+        Self::CreatedAt,
+        Self::CompletedAt,
+        Self::DeletedAt,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Id => "id",
+            Self::Title => "title",
+            Self::Priority => "priority",
+            Self::CreatedAt => "created_at",
+            Self::CompletedAt => "completed_at",
+            Self::DeletedAt => "deleted_at",
+        }
+    }
+
+    pub fn value_of(&self, task: &tasks::Model) -> SortValue {
+        match self {
+            Self::Id => SortValue::Int(task.id),
+            Self::Title => SortValue::Text(Some(task.title.clone())),
+            Self::Priority => SortValue::Text(task.priority.clone()),
+            Self::CreatedAt => SortValue::Time(task.created_at),
+            Self::CompletedAt => SortValue::Time(task.completed_at),
+            Self::DeletedAt => SortValue::Time(task.deleted_at),
+        }
+    }
+}
+impl fmt::Display for TaskSortField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+impl FromStr for TaskSortField {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|field| field.as_str() == s)
+            .ok_or_else(|| format!("unknown sort field `{s}`"))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskSort {
+    pub field: TaskSortField,
+    pub descending: bool,
+}
+
+/// The value of a sort column in one row. NULL sorts after every other value on every backend
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SortValue {
+    Int(i32),
+    Text(Option<String>),
+    Time(Option<DateTimeWithTimeZone>),
+}
+
+/// Which part of the matching tasks `TaskRepository::list` returns
+#[derive(Debug, Clone)]
+pub struct TaskPage {
+    /// The last key has to be unique, `id`, so the order is total
+    pub sort: Vec<TaskSort>,
+    /// Keyset pagination: the values of the sort keys in the last row of the previous page,
+    /// the page starts right after that row
+    pub after: Option<Vec<SortValue>>,
+    pub offset: u64,
+    pub limit: u64,
+}
+
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    async fn create(&self, user: NewUser) -> Result<users::Model, DbErr>;
+    async fn find_by_id(&self, id: i32) -> Result<Option<users::Model>, DbErr>;
+    async fn find_by_username(&self, username: &str) -> Result<Option<users::Model>, DbErr>;
+    async fn find_by_email(&self, email: &str) -> Result<Option<users::Model>, DbErr>;
+    /// Overwrite every column of the user with this id, `RecordNotUpdated` when there is none
+    async fn update(&self, user: users::Model) -> Result<users::Model, DbErr>;
+}
+
+#[async_trait]
+pub trait TaskRepository: Send + Sync {
+    async fn create(&self, task: NewTask) -> Result<tasks::Model, DbErr>;
+    /// Soft deleted tasks are returned too
+    async fn find_by_id(&self, id: i32) -> Result<Option<tasks::Model>, DbErr>;
+    /// One page of the tasks that are not soft deleted, or of those that are with `filter.deleted`
+    async fn list(&self, filter: TaskFilter, page: TaskPage) -> Result<Vec<tasks::Model>, DbErr>;
+    /// How many tasks `list` would return without paging
+    async fn count(&self, filter: TaskFilter) -> Result<u64, DbErr>;
+    /// Overwrite every column of the task with this id and bump its version. Only when the
+    /// stored version is still `task.version`, `RecordNotUpdated` when it changed since the task
+    /// was read or there is no such task
+    async fn update(&self, task: tasks::Model) -> Result<tasks::Model, DbErr>;
+    /// Remove the row only when its version is still `version`, `RecordNotUpdated` when it changed
+    /// since the task was read or there is no such task
+    async fn delete_versioned(&self, id: i32, version: i32) -> Result<(), DbErr>;
+    /// Remove the tasks soft deleted before this time, returns how many
+    async fn purge_deleted(&self, before: DateTimeWithTimeZone) -> Result<u64, DbErr>;
+}
+
+#[async_trait]
+pub trait SessionRepository: Send + Sync {
+    async fn create(&self, session: NewSession) -> Result<sessions::Model, DbErr>;
+    async fn find_by_id(&self, id: &str) -> Result<Option<sessions::Model>, DbErr>;
+    /// Sessions of the user that are neither revoked nor expired, most recently seen first
+    async fn list_active(&self, user_id: i32) -> Result<Vec<sessions::Model>, DbErr>;
+    /// Set `last_seen_at`, false when the session is revoked or missing
+    async fn touch(&self, id: &str, last_seen_at: DateTimeWithTimeZone) -> Result<bool, DbErr>;
+    /// Set `last_seen_at` and `expires_at`, false when the session is revoked or missing
+    async fn extend(
+        &self,
+        id: &str,
+        last_seen_at: DateTimeWithTimeZone,
+        expires_at: DateTimeWithTimeZone,
+    ) -> Result<bool, DbErr>;
+    /// False when the session was revoked already or is missing
+    async fn revoke(&self, id: &str) -> Result<bool, DbErr>;
+    /// Revoke every session of the user that is not revoked yet, returns how many there were
+    async fn revoke_all(&self, user_id: i32) -> Result<u64, DbErr>;
+}
+
+#[async_trait]
+pub trait RefreshTokenRepository: Send + Sync {
+    async fn create(&self, token: NewRefreshToken) -> Result<refresh_tokens::Model, DbErr>;
+    async fn find_by_hash(&self, token_hash: &str) -> Result<Option<refresh_tokens::Model>, DbErr>;
+    /// Set `used_at` unless the token was used or revoked already, returns false then.
+    /// This is a single conditional update so two concurrent refreshes can't both win
+    async fn mark_used(&self, id: i32) -> Result<bool, DbErr>;
+    /// Revoke every token of the family that is not revoked yet
+    async fn revoke_family(&self, family_id: &str) -> Result<(), DbErr>;
+}
+
+/// Single use tokens mailed to a user, for email verification and password resets
+#[async_trait]
+pub trait UserTokenRepository: Send + Sync {
+    async fn create(&self, token: NewUserToken) -> Result<user_tokens::Model, DbErr>;
+    async fn find_by_hash(
+        &self,
+        kind: UserTokenKind,
+        token_hash: &str,
+    ) -> Result<Option<user_tokens::Model>, DbErr>;
+    /// Set `used_at` unless it is set already, returns false then
+    async fn mark_used(&self, id: i32) -> Result<bool, DbErr>;
+    /// Mark every unused token of this kind of the user as used
+    async fn use_all(&self, user_id: i32, kind: UserTokenKind) -> Result<(), DbErr>;
+}
+
+#[async_trait]
+pub trait ApiKeyRepository: Send + Sync {
+    async fn create(&self, key: NewApiKey) -> Result<api_keys::Model, DbErr>;
+    async fn find_by_id(&self, id: i32) -> Result<Option<api_keys::Model>, DbErr>;
+    async fn find_by_prefix(&self, prefix: &str) -> Result<Option<api_keys::Model>, DbErr>;
+    /// Every key of the user, expired ones too, newest first
+    async fn list(&self, user_id: i32) -> Result<Vec<api_keys::Model>, DbErr>;
+    /// Overwrite every column of the key with this id, `RecordNotUpdated` when there is none
+    async fn update(&self, key: api_keys::Model) -> Result<api_keys::Model, DbErr>;
+    /// Remove the row, returns false when there was none
+    async fn delete(&self, id: i32) -> Result<bool, DbErr>;
+    /// Remove every key of the user, returns how many there were
+    async fn delete_all(&self, user_id: i32) -> Result<u64, DbErr>;
+}
+
+pub type DynUserRepository = Arc<dyn UserRepository>;
+pub type DynTaskRepository = Arc<dyn TaskRepository>;
+pub type DynSessionRepository = Arc<dyn SessionRepository>;
+pub type DynRefreshTokenRepository = Arc<dyn RefreshTokenRepository>;
+pub type DynUserTokenRepository = Arc<dyn UserTokenRepository>;
+pub type DynApiKeyRepository = Arc<dyn ApiKeyRepository>;
@@ -0,0 +1,797 @@
+use axum::async_trait;
+use chrono::Utc;
+use sea_orm::{
+    prelude::DateTimeWithTimeZone,
+    sea_query::{Expr, Func, LikeExpr},
+    ActiveModelTrait,
+    ActiveValue::Unchanged,
+    ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, DbBackend, DbErr, EntityTrait,
+    Order, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Set, Value,
+};
+
+use super::{
+    ApiKeyRepository, NewApiKey, NewRefreshToken, NewSession, NewTask, NewUser, NewUserToken,
+    RefreshTokenRepository, SessionRepository, SortValue, TaskFilter, TaskPage, TaskRepository,
+    TaskSearch, TaskSort, TaskSortField, UserRepository, UserTokenKind, UserTokenRepository,
+};
+use crate::entities::{
+    api_keys,
+    prelude::{ApiKeys, RefreshTokens, Sessions, Tasks, UserTokens, Users},
+    refresh_tokens, sessions, tasks, user_tokens, users,
+};
+
+pub struct SeaOrmUserRepository {
+    db_conn: DatabaseConnection,
+}
+impl SeaOrmUserRepository {
+    pub fn new(db_conn: DatabaseConnection) -> Self {
+        Self { db_conn }
+    }
+}
+
+#[async_trait]
+impl UserRepository for SeaOrmUserRepository {
+    async fn create(&self, user: NewUser) -> Result<users::Model, DbErr> {
+        users::ActiveModel {
+            username: Set(user.username),
+            password: Set(user.password),
+            role: Set(user.role),
+            email: Set(user.email),
+            ..Default::default()
+        }
+        .insert(&self.db_conn)
+        .await
+    }
+
+    async fn find_by_id(&self, id: i32) -> Result<Option<users::Model>, DbErr> {
+        Users::find_by_id(id).one(&self.db_conn).await
+    }
+
+    async fn find_by_username(&self, username: &str) -> Result<Option<users::Model>, DbErr> {
+        Users::find()
+            .filter(users::Column::Username.eq(username))
+            .one(&self.db_conn)
+            .await
+    }
+
+    async fn find_by_email(&self, email: &str) -> Result<Option<users::Model>, DbErr> {
+        Users::find()
+            .filter(users::Column::Email.eq(email))
+            .one(&self.db_conn)
+            .await
+    }
+
+    async fn update(&self, user: users::Model) -> Result<users::Model, DbErr> {
+        users::ActiveModel {
+            id: Unchanged(user.id),
+            username: Set(user.username),
+            password: Set(user.password),
+            deleted_at: Set(user.deleted_at),
+            role: Set(user.role),
+            email: Set(user.email),
+            email_verified_at: Set(user.email_verified_at),
+        }
+        .update(&self.db_conn)
+        .await
+    }
+}
+
+pub struct SeaOrmTaskRepository {
+    db_conn: DatabaseConnection,
+}
+impl SeaOrmTaskRepository {
+    pub fn new(db_conn: DatabaseConnection) -> Self {
+        Self { db_conn }
+    }
+}
+
+#[async_trait]
+impl TaskRepository for SeaOrmTaskRepository {
+    async fn create(&self, task: NewTask) -> Result<tasks::Model, DbErr> {
+        tasks::ActiveModel {
+            title: Set(task.title),
+            priority: Set(task.priority),
+            description: Set(task.description),
+            user_id: Set(task.user_id),
+            created_at: Set(Some(Utc::now().into())),
+            version: Set(1),
+            ..Default::default()
+        }
+        .insert(&self.db_conn)
+        .await
+    }
+
+    async fn find_by_id(&self, id: i32) -> Result<Option<tasks::Model>, DbErr> {
+        Tasks::find_by_id(id).one(&self.db_conn).await
+    }
+
+    async fn list(&self, filter: TaskFilter, page: TaskPage) -> Result<Vec<tasks::Model>, DbErr> {
+        let mut condition = task_condition(filter, self.db_conn.get_database_backend());
+        if let Some(after) = &page.after {
+            condition = condition.add(after_condition(&page.sort, after));
+        }
+        let mut query = Tasks::find().filter(condition);
+        for sort in &page.sort {
+            let column = sort_column(sort.field);
+            let order = if sort.descending {
+                Order::Desc
+            } else {
+                Order::Asc
+            };
+            // NULLs last when ascending, first when descending, the same on every backend
+            if !matches!(sort.field, TaskSortField::Id | TaskSortField::Title) {
+                query = query.order_by(Expr::col(column).is_null(), order.clone());
+            }
+            query = query.order_by(column, order);
+        }
+        query
+            .offset(page.offset)
+            .limit(page.limit)
+            .all(&self.db_conn)
+            .await
+    }
+
+    async fn count(&self, filter: TaskFilter) -> Result<u64, DbErr> {
+        Tasks::find()
+            .filter(task_condition(filter, self.db_conn.get_database_backend()))
+            .count(&self.db_conn)
+            .await
+    }
+
+    async fn update(&self, task: tasks::Model) -> Result<tasks::Model, DbErr> {
+        let model = tasks::ActiveModel {
+            id: Unchanged(task.id),
+            priority: Set(task.priority),
+            title: Set(task.title),
+            completed_at: Set(task.completed_at),
+            description: Set(task.description),
+            deleted_at: Set(task.deleted_at),
+            user_id: Set(task.user_id),
+            is_default: Set(task.is_default),
+            created_at: Set(task.created_at),
+            version: Set(task.version + 1),
+        };
+        // compare and set in one statement, a concurrent update makes it match no row
+        Tasks::update(model)
+            .filter(tasks::Column::Version.eq(task.version))
+            .exec(&self.db_conn)
+            .await
+    }
+
+    async fn delete_versioned(&self, id: i32, version: i32) -> Result<(), DbErr> {
+        let result = Tasks::delete_by_id(id)
+            .filter(tasks::Column::Version.eq(version))
+            .exec(&self.db_conn)
+            .await?;
+        if result.rows_affected == 0 {
+            return Err(DbErr::RecordNotUpdated);
+        }
+        Ok(())
+    }
+
+    async fn purge_deleted(&self, before: DateTimeWithTimeZone) -> Result<u64, DbErr> {
+        let result = Tasks::delete_many()
+            .filter(tasks::Column::DeletedAt.lt(before))
+            .exec(&self.db_conn)
+            .await?;
+        Ok(result.rows_affected)
+    }
+}
+
+fn task_condition(filter: TaskFilter, backend: DbBackend) -> Condition {
+    let mut condition = Condition::all().add(if filter.deleted {
+        tasks::Column::DeletedAt.is_not_null()
+    } else {
+        tasks::Column::DeletedAt.is_null()
+    });
+    if let Some(user_id) = filter.user_id {
+        condition = condition.add(tasks::Column::UserId.eq(user_id));
+    }
+    if let Some(ids) = filter.ids {
+        condition = condition.add(tasks::Column::Id.is_in(ids));
+    }
+    if let Some(priorities) = filter.priorities {
+        let mut any = Condition::any();
+        if priorities.contains(&None) {
+            any = any.add(tasks::Column::Priority.is_null());
+        }
+        any = any.add(tasks::Column::Priority.is_in(priorities.into_iter().flatten()));
+        condition = condition.add(any);
+    }
+    if let Some(title) = filter.title {
+        condition = match title {
+            None => condition.add(tasks::Column::Title.is_null()),
+            Some(title) => condition.add(tasks::Column::Title.eq(title)),
+        };
+    }
+    if let Some(completed) = filter.completed {
+        condition = if completed {
+            condition.add(tasks::Column::CompletedAt.is_not_null())
+        } else {
+            condition.add(tasks::Column::CompletedAt.is_null())
+        };
+    }
+    if let Some(is_default) = filter.is_default {
+        condition = if is_default {
+            condition.add(tasks::Column::IsDefault.eq(true))
+        } else {
+            condition.add(
+                Condition::any()
+                    .add(tasks::Column::IsDefault.eq(false))
+                    .add(tasks::Column::IsDefault.is_null()),
+            )
+        };
+    }
+    if let Some(from) = filter.created_from {
+        condition = condition.add(tasks::Column::CreatedAt.gte(from));
+    }
+    if let Some(to) = filter.created_to {
+        condition = condition.add(tasks::Column::CreatedAt.lt(to));
+    }
+    if let Some(from) = filter.completed_from {
+        condition = condition.add(tasks::Column::CompletedAt.gte(from));
+    }
+    if let Some(to) = filter.completed_to {
+        condition = condition.add(tasks::Column::CompletedAt.lt(to));
+    }
+    if let Some(search) = filter.search {
+        condition = condition.add(search_condition(search, backend));
+    }
+    condition
+}
+
+fn search_condition(search: TaskSearch, backend: DbBackend) -> Condition {
+    let text = match search {
+        TaskSearch::FullText(text) if backend == DbBackend::Postgres => {
+            return Condition::all().add(Expr::cust_with_values(
+                "to_tsvector('english', \"title\" || ' ' || coalesce(\"description\", '')) @@ websearch_to_tsquery('english', $1)",
+                [text],
+            ));
+        }
+        TaskSearch::Substring(text) | TaskSearch::FullText(text) => text,
+    };
+    // % and _ in the text are no wildcards
+    let pattern = format!(
+        "%{}%",
+        text.to_lowercase()
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    );
+    let like = |column: tasks::Column| {
+        Expr::expr(Func::lower(Expr::col(column))).like(LikeExpr::new(pattern.clone()).escape('\\'))
+    };
+    Condition::any()
+        .add(like(tasks::Column::Title))
+        .add(like(tasks::Column::Description))
+}
+
+fn sort_column(field: TaskSortField) -> tasks::Column {
+    match field {
+        TaskSortField::Id => tasks::Column::Id,
+        TaskSortField::Title => tasks::Column::Title,
+        TaskSortField::Priority => tasks::Column::Priority,
+        TaskSortField::CreatedAt => tasks::Column::CreatedAt,
+        TaskSortField::CompletedAt => tasks::Column::CompletedAt,
+        TaskSortField::DeletedAt => tasks::Column::DeletedAt,
+    }
+}
+
+// None for NULL
+fn sort_value(value: &SortValue) -> Option<Value> {
+    match value.clone() {
+        SortValue::Int(value) => Some(value.into()),
+        SortValue::Text(value) => value.map(Value::from),
+        SortValue::Time(value) => value.map(Value::from),
+    }
+}
+
+// rows after the cursor row in the sort order: (a > x) or (a = x and b > y) or ..
+fn after_condition(sort: &[TaskSort], after: &[SortValue]) -> Condition {
+    let mut any = Condition::any();
+    for (i, (key, value)) in sort.iter().zip(after).enumerate() {
+        let column = sort_column(key.field);
+        // NULL is the greatest value
+        let beyond = match (key.descending, sort_value(value)) {
+            (false, Some(value)) => Condition::any().add(column.gt(value)).add(column.is_null()),
+            // nothing sorts after NULL
+            (false, None) => continue,
+            (true, Some(value)) => Condition::all().add(column.lt(value)),
+            (true, None) => Condition::all().add(column.is_not_null()),
+        };
+        let mut branch = Condition::all();
+        for (key, value) in sort[..i].iter().zip(after) {
+            let column = sort_column(key.field);
+            branch = match sort_value(value) {
+                Some(value) => branch.add(column.eq(value)),
+                None => branch.add(column.is_null()),
+            };
+        }
+        any = any.add(branch.add(beyond));
+    }
+    any
+}
+
+pub struct SeaOrmSessionRepository {
+    db_conn: DatabaseConnection,
+}
+impl SeaOrmSessionRepository {
+    pub fn new(db_conn: DatabaseConnection) -> Self {
+        Self { db_conn }
+    }
+}
+
+#[async_trait]
+impl SessionRepository for SeaOrmSessionRepository {
+    async fn create(&self, session: NewSession) -> Result<sessions::Model, DbErr> {
+        let now: DateTimeWithTimeZone = Utc::now().into();
+        sessions::ActiveModel {
+            id: Set(session.id),
+            user_id: Set(session.user_id),
+            user_agent: Set(session.user_agent),
+            ip: Set(session.ip),
+            created_at: Set(now),
+            last_seen_at: Set(now),
+            expires_at: Set(session.expires_at),
+            revoked_at: Set(None),
+        }
+        .insert(&self.db_conn)
+        .await
+    }
+
+    async fn find_by_id(&self, id: &str) -> Result<Option<sessions::Model>, DbErr> {
+        Sessions::find_by_id(id.to_owned()).one(&self.db_conn).await
+    }
+
+    async fn list_active(&self, user_id: i32) -> Result<Vec<sessions::Model>, DbErr> {
+        let now: DateTimeWithTimeZone = Utc::now().into();
+        Sessions::find()
+            .filter(sessions::Column::UserId.eq(user_id))
+            .filter(sessions::Column::RevokedAt.is_null())
+            .filter(sessions::Column::ExpiresAt.gt(now))
+            .order_by_desc(sessions::Column::LastSeenAt)
+            .all(&self.db_conn)
+            .await
+    }
+
+    // targeted updates, a write from a stale read would bring a revoked session back
+    async fn touch(&self, id: &str, last_seen_at: DateTimeWithTimeZone) -> Result<bool, DbErr> {
+        let result = Sessions::update_many()
+            .col_expr(sessions::Column::LastSeenAt, Expr::value(last_seen_at))
+            .filter(sessions::Column::Id.eq(id))
+            .filter(sessions::Column::RevokedAt.is_null())
+            .exec(&self.db_conn)
+            .await?;
+        Ok(result.rows_affected == 1)
+    }
+
+    async fn extend(
+        &self,
+        id: &str,
+        last_seen_at: DateTimeWithTimeZone,
+        expires_at: DateTimeWithTimeZone,
+    ) -> Result<bool, DbErr> {
+        let result = Sessions::update_many()
+            .col_expr(sessions::Column::LastSeenAt, Expr::value(last_seen_at))
+            .col_expr(sessions::Column::ExpiresAt, Expr::value(expires_at))
+            .filter(sessions::Column::Id.eq(id))
+            .filter(sessions::Column::RevokedAt.is_null())
+            .exec(&self.db_conn)
+            .await?;
+        Ok(result.rows_affected == 1)
+    }
+
+    async fn revoke(&self, id: &str) -> Result<bool, DbErr> {
+        let now: DateTimeWithTimeZone = Utc::now().into();
+        let result = Sessions::update_many()
+            .col_expr(sessions::Column::RevokedAt, Expr::value(now))
+            .filter(sessions::Column::Id.eq(id))
+            .filter(sessions::Column::RevokedAt.is_null())
+            .exec(&self.db_conn)
+            .await?;
+        Ok(result.rows_affected == 1)
+    }
+
+    async fn revoke_all(&self, user_id: i32) -> Result<u64, DbErr> {
+        let now: DateTimeWithTimeZone = Utc::now().into();
+        let result = Sessions::update_many()
+            .col_expr(sessions::Column::RevokedAt, Expr::value(now))
+            .filter(sessions::Column::UserId.eq(user_id))
+            .filter(sessions::Column::RevokedAt.is_null())
+            .exec(&self.db_conn)
+            .await?;
+        Ok(result.rows_affected)
+    }
+}
+
+pub struct SeaOrmRefreshTokenRepository {
+    db_conn: DatabaseConnection,
+}
+impl SeaOrmRefreshTokenRepository {
+    pub fn new(db_conn: DatabaseConnection) -> Self {
+        Self { db_conn }
+    }
+}
+
+#[async_trait]
+impl RefreshTokenRepository for SeaOrmRefreshTokenRepository {
+    async fn create(&self, token: NewRefreshToken) -> Result<refresh_tokens::Model, DbErr> {
+        refresh_tokens::ActiveModel {
+            user_id: Set(token.user_id),
+            family_id: Set(token.family_id),
+            token_hash: Set(token.token_hash),
+            created_at: Set(Utc::now().into()),
+            expires_at: Set(token.expires_at),
+            ..Default::default()
+        }
+        .insert(&self.db_conn)
+        .await
+    }
+
+    async fn find_by_hash(&self, token_hash: &str) -> Result<Option<refresh_tokens::Model>, DbErr> {
+        RefreshTokens::find()
+            .filter(refresh_tokens::Column::TokenHash.eq(token_hash))
+            .one(&self.db_conn)
+            .await
+    }
+
+    async fn mark_used(&self, id: i32) -> Result<bool, DbErr> {
+        let now: DateTimeWithTimeZone = Utc::now().into();
+        let result = RefreshTokens::update_many()
+            .col_expr(refresh_tokens::Column::UsedAt, Expr::value(now))
+            .filter(refresh_tokens::Column::Id.eq(id))
+            .filter(refresh_tokens::Column::UsedAt.is_null())
+            .filter(refresh_tokens::Column::RevokedAt.is_null())
+            .exec(&self.db_conn)
+            .await?;
+        Ok(result.rows_affected == 1)
+    }
+
+    async fn revoke_family(&self, family_id: &str) -> Result<(), DbErr> {
+        let now: DateTimeWithTimeZone = Utc::now().into();
+        RefreshTokens::update_many()
+            .col_expr(refresh_tokens::Column::RevokedAt, Expr::value(now))
+            .filter(refresh_tokens::Column::FamilyId.eq(family_id))
+            .filter(refresh_tokens::Column::RevokedAt.is_null())
+            .exec(&self.db_conn)
+            .await?;
+        Ok(())
+    }
+}
+
+pub struct SeaOrmUserTokenRepository {
+    db_conn: DatabaseConnection,
+}
+impl SeaOrmUserTokenRepository {
+    pub fn new(db_conn: DatabaseConnection) -> Self {
+        Self { db_conn }
+    }
+}
+
+#[async_trait]
+impl UserTokenRepository for SeaOrmUserTokenRepository {
+    async fn create(&self, token: NewUserToken) -> Result<user_tokens::Model, DbErr> {
+        user_tokens::ActiveModel {
+            user_id: Set(token.user_id),
+            kind: Set(token.kind.as_str().to_owned()),
+            token_hash: Set(token.token_hash),
+            email: Set(token.email),
+            created_at: Set(Utc::now().into()),
+            expires_at: Set(token.expires_at),
+            ..Default::default()
+        }
+        .insert(&self.db_conn)
+        .await
+    }
+
+    async fn find_by_hash(
+        &self,
+        kind: UserTokenKind,
+        token_hash: &str,
+    ) -> Result<Option<user_tokens::Model>, DbErr> {
+        UserTokens::find()
+            .filter(user_tokens::Column::Kind.eq(kind.as_str()))
+            .filter(user_tokens::Column::TokenHash.eq(token_hash))
+            .one(&self.db_conn)
+            .await
+    }
+
+    async fn mark_used(&self, id: i32) -> Result<bool, DbErr> {
+        let now: DateTimeWithTimeZone = Utc::now().into();
+        let result = UserTokens::update_many()
+            .col_expr(user_tokens::Column::UsedAt, Expr::value(now))
+            .filter(user_tokens::Column::Id.eq(id))
+            .filter(user_tokens::Column::UsedAt.is_null())
+            .exec(&self.db_conn)
+            .await?;
+        Ok(result.rows_affected == 1)
+    }
+
+    async fn use_all(&self, user_id: i32, kind: UserTokenKind) -> Result<(), DbErr> {
+        let now: DateTimeWithTimeZone = Utc::now().into();
+        UserTokens::update_many()
+            .col_expr(user_tokens::Column::UsedAt, Expr::value(now))
+            .filter(user_tokens::Column::UserId.eq(user_id))
+            .filter(user_tokens::Column::Kind.eq(kind.as_str()))
+            .filter(user_tokens::Column::UsedAt.is_null())
+            .exec(&self.db_conn)
+            .await?;
+        Ok(())
+    }
+}
+
+pub struct SeaOrmApiKeyRepository {
+    db_conn: DatabaseConnection,
+}
+impl SeaOrmApiKeyRepository {
+    pub fn new(db_conn: DatabaseConnection) -> Self {
+        Self { db_conn }
+    }
+}
+
+#[async_trait]
+impl ApiKeyRepository for SeaOrmApiKeyRepository {
+    async fn create(&self, key: NewApiKey) -> Result<api_keys::Model, DbErr> {
+        api_keys::ActiveModel {
+            user_id: Set(key.user_id),
+            name: Set(key.name),
+            prefix: Set(key.prefix),
+            key_hash: Set(key.key_hash),
+            scopes: Set(key.scopes),
+            created_at: Set(Utc::now().into()),
+            expires_at: Set(key.expires_at),
+            ..Default::default()
+        }
+        .insert(&self.db_conn)
+        .await
+    }
+
+    async fn find_by_id(&self, id: i32) -> Result<Option<api_keys::Model>, DbErr> {
+        ApiKeys::find_by_id(id).one(&self.db_conn).await
+    }
+
+    async fn find_by_prefix(&self, prefix: &str) -> Result<Option<api_keys::Model>, DbErr> {
+        ApiKeys::find()
+            .filter(api_keys::Column::Prefix.eq(prefix))
+            .one(&self.db_conn)
+            .await
+    }
+
+    async fn list(&self, user_id: i32) -> Result<Vec<api_keys::Model>, DbErr> {
+        ApiKeys::find()
+            .filter(api_keys::Column::UserId.eq(user_id))
+            .order_by_desc(api_keys::Column::Id)
+            .all(&self.db_conn)
+            .await
+    }
+
+    async fn update(&self, key: api_keys::Model) -> Result<api_keys::Model, DbErr> {
+        api_keys::ActiveModel {
+            id: Unchanged(key.id),
+            user_id: Set(key.user_id),
+            name: Set(key.name),
+            prefix: Set(key.prefix),
+            key_hash: Set(key.key_hash),
+            scopes: Set(key.scopes),
+            created_at: Set(key.created_at),
+            expires_at: Set(key.expires_at),
+            last_used_at: Set(key.last_used_at),
+        }
+        .update(&self.db_conn)
+        .await
+    }
+
+    async fn delete(&self, id: i32) -> Result<bool, DbErr> {
+        let result = ApiKeys::delete_by_id(id).exec(&self.db_conn).await?;
+        Ok(result.rows_affected == 1)
+    }
+
+    async fn delete_all(&self, user_id: i32) -> Result<u64, DbErr> {
+        let result = ApiKeys::delete_many()
+            .filter(api_keys::Column::UserId.eq(user_id))
+            .exec(&self.db_conn)
+            .await?;
+        Ok(result.rows_affected)
+    }
+}
+
+// queries against a real database, run with `cargo test --features sqlite`
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::{
+        config::{DatabaseConfig, PoolConfig},
+        database::connect_db,
+        migration::{Migrator, MigratorTrait},
+    };
+
+    async fn database() -> DatabaseConnection {
+        let database = DatabaseConfig {
+            url: "sqlite::memory:".to_owned(),
+            pool: PoolConfig {
+                max_connections: None,
+                min_connections: None,
+                connect_timeout: None,
+                acquire_timeout: None,
+                idle_timeout: None,
+                max_lifetime: None,
+                sqlx_logging: false,
+            },
+        };
+        let db_conn = connect_db(&database).await.unwrap();
+        Migrator::up(&db_conn, None).await.unwrap();
+        db_conn
+    }
+
+    async fn repository() -> SeaOrmTaskRepository {
+        SeaOrmTaskRepository::new(database().await)
+    }
+
+    // ids 1 to 5 with priorities A, -, B, -, A, completed at 2, -, 1, 3, -
+    async fn seeded() -> SeaOrmTaskRepository {
+        let tasks = repository().await;
+        let rows = [
+            (Some("A"), Some("2026-01-02T00:00:00+00:00")),
+            (None, None),
+            (Some("B"), Some("2026-01-01T00:00:00+00:00")),
+            (None, Some("2026-01-03T00:00:00+00:00")),
+            (Some("A"), None),
+        ];
+        for (priority, completed_at) in rows {
+            let mut task = tasks
+                .create(NewTask {
+                    title: "task".to_owned(),
+                    priority: priority.map(str::to_owned),
+                    description: None,
+                    user_id: None,
+                })
+                .await
+                .unwrap();
+            task.completed_at = completed_at.map(|at| DateTime::parse_from_rfc3339(at).unwrap());
+            tasks.update(task).await.unwrap();
+        }
+        tasks
+    }
+
+    fn sort(keys: &[(TaskSortField, bool)]) -> Vec<TaskSort> {
+        keys.iter()
+            .map(|&(field, descending)| TaskSort { field, descending })
+            .collect()
+    }
+
+    async fn page(
+        tasks: &SeaOrmTaskRepository,
+        sort: &[TaskSort],
+        after: Option<Vec<SortValue>>,
+        limit: u64,
+    ) -> Vec<tasks::Model> {
+        let page = TaskPage {
+            sort: sort.to_vec(),
+            after,
+            offset: 0,
+            limit,
+        };
+        tasks.list(TaskFilter::default(), page).await.unwrap()
+    }
+
+    // every page starts after the last row of the one before, like clients follow next_cursor
+    async fn walk(tasks: &SeaOrmTaskRepository, sort: &[TaskSort], limit: u64) -> Vec<i32> {
+        let mut ids = Vec::new();
+        let mut after = None;
+        loop {
+            let rows = page(tasks, sort, after, limit).await;
+            ids.extend(rows.iter().map(|task| task.id));
+            match rows.last() {
+                Some(last) if rows.len() as u64 == limit => {
+                    after = Some(sort.iter().map(|key| key.field.value_of(last)).collect());
+                }
+                _ => return ids,
+            }
+        }
+    }
+
+    async fn assert_order(
+        tasks: &SeaOrmTaskRepository,
+        keys: &[(TaskSortField, bool)],
+        ids: &[i32],
+    ) {
+        let sort = sort(keys);
+        let all: Vec<i32> = page(tasks, &sort, None, 100)
+            .await
+            .iter()
+            .map(|task| task.id)
+            .collect();
+        assert_eq!(all, ids, "{keys:?}");
+        for limit in [1, 2, 3] {
+            assert_eq!(walk(tasks, &sort, limit).await, ids, "{keys:?} by {limit}");
+        }
+    }
+
+    use TaskSortField::{CompletedAt, Id, Priority};
+
+    #[tokio::test]
+    async fn nulls_last_ascending() {
+        let tasks = seeded().await;
+        assert_order(&tasks, &[(Priority, false), (Id, false)], &[1, 5, 3, 2, 4]).await;
+        assert_order(
+            &tasks,
+            &[(CompletedAt, false), (Id, false)],
+            &[3, 1, 4, 2, 5],
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn nulls_first_descending() {
+        let tasks = seeded().await;
+        assert_order(&tasks, &[(Priority, true), (Id, false)], &[2, 4, 3, 1, 5]).await;
+        assert_order(&tasks, &[(CompletedAt, true), (Id, true)], &[5, 2, 4, 1, 3]).await;
+    }
+
+    #[tokio::test]
+    async fn mixed_directions() {
+        let tasks = seeded().await;
+        assert_order(
+            &tasks,
+            &[(Priority, false), (CompletedAt, true), (Id, false)],
+            &[5, 1, 3, 2, 4],
+        )
+        .await;
+        assert_order(&tasks, &[(Id, true)], &[5, 4, 3, 2, 1]).await;
+    }
+
+    #[tokio::test]
+    async fn delete_versioned_keeps_a_changed_task() {
+        let tasks = seeded().await;
+        // seeding updated every task once
+        let task = tasks.find_by_id(1).await.unwrap().unwrap();
+        assert_eq!(task.version, 2);
+        let stale = tasks.delete_versioned(1, 1).await;
+        assert!(matches!(stale, Err(DbErr::RecordNotUpdated)));
+        assert!(tasks.find_by_id(1).await.unwrap().is_some());
+        tasks.delete_versioned(1, 2).await.unwrap();
+        assert!(tasks.find_by_id(1).await.unwrap().is_none());
+        let gone = tasks.delete_versioned(1, 2).await;
+        assert!(matches!(gone, Err(DbErr::RecordNotUpdated)));
+    }
+
+    #[tokio::test]
+    async fn reused_refresh_token_is_refused_and_revokes_its_family() {
+        let db_conn = database().await;
+        let users = SeaOrmUserRepository::new(db_conn.clone());
+        let refresh_tokens = SeaOrmRefreshTokenRepository::new(db_conn);
+        let user = users
+            .create(NewUser {
+                username: "alice".to_owned(),
+                password: "not a hash".to_owned(),
+                role: "user".to_owned(),
+                email: None,
+            })
+            .await
+            .unwrap();
+        let expires_at: DateTimeWithTimeZone = (Utc::now() + chrono::Duration::hours(1)).into();
+        let token = |family_id: &str, token_hash: &str| NewRefreshToken {
+            user_id: user.id,
+            family_id: family_id.to_owned(),
+            token_hash: token_hash.to_owned(),
+            expires_at,
+        };
+        let first = refresh_tokens.create(token("a", "first")).await.unwrap();
+        refresh_tokens.create(token("a", "next")).await.unwrap();
+        refresh_tokens.create(token("b", "other")).await.unwrap();
+
+        assert!(refresh_tokens.mark_used(first.id).await.unwrap());
+        // the second use of a token is how the refresh handler spots a stolen one
+        assert!(!refresh_tokens.mark_used(first.id).await.unwrap());
+        refresh_tokens.revoke_family("a").await.unwrap();
+
+        let next = refresh_tokens.find_by_hash("next").await.unwrap().unwrap();
+        assert!(next.revoked_at.is_some());
+        assert!(!refresh_tokens.mark_used(next.id).await.unwrap());
+        let other = refresh_tokens.find_by_hash("other").await.unwrap().unwrap();
+        assert!(other.revoked_at.is_none());
+        assert!(refresh_tokens.mark_used(other.id).await.unwrap());
+    }
+}
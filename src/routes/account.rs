@@ -0,0 +1,313 @@
+//! Email verification and passwords. Sign up mails a single use token to the address, confirming
+//! it at `/users/verify-email` sets `email_verified_at`. A forgotten password is reset with a token
+//! mailed the same way, and a logged in user can change it with the old one.
+use axum::{extract::State, Extension, Json};
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use validator::Validate;
+
+use crate::{
+    config::Config,
+    entities::users::Model as UserModel,
+    error::AppError,
+    mailer::{DynMailer, Mail},
+    repository::{
+        DynApiKeyRepository, DynSessionRepository, DynUserRepository, DynUserTokenRepository,
+        NewUserToken, UserTokenKind,
+    },
+    shutdown::Shutdown,
+    utils::{hash_password, hash_token, make_random_token, verify_password},
+    validation::{validate_password, ValidatedJson, ValidatedQuery},
+};
+
+/// Stores a new verification token for the current email of the user and mails it
+pub async fn send_verification(
+    user_tokens: &DynUserTokenRepository,
+    mailer: &DynMailer,
+    config: &Config,
+    user: &UserModel,
+) -> Result<(), AppError> {
+    let Some(email) = user.email.clone() else {
+        return Err(AppError::validation("no email address to verify"));
+    };
+    let token = make_random_token();
+    let ttl = chrono::Duration::seconds(config.auth.email_verify_ttl.as_secs() as i64);
+    user_tokens
+        .create(NewUserToken {
+            user_id: user.id,
+            kind: UserTokenKind::VerifyEmail,
+            token_hash: hash_token(&token),
+            email: Some(email.clone()),
+            expires_at: (Utc::now() + ttl).into(),
+        })
+        .await?;
+
+    let link = match &config.mail.public_url {
+        Some(public_url) => format!("{public_url}/users/verify-email?token={token}"),
+        None => format!("POST /users/verify-email {{\"token\": \"{token}\"}}"),
+    };
+    mailer
+        .send(Mail {
+            to: email,
+            subject: "Verify your email address".to_owned(),
+            body: format!(
+                "Hi {},\n\nconfirm your email address with\n\n{link}\n\nThe link expires in {} hours.",
+                user.username,
+                ttl.num_hours()
+            ),
+        })
+        .await
+        .map_err(|err| AppError::Upstream(format!("sending mail failed: {err}")))
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct VerifyEmail {
+    pub token: String,
+}
+#[derive(Serialize, Debug)]
+pub struct ResponseVerifyEmail {
+    pub user_id: i32,
+    pub email: String,
+    pub email_verified_at: DateTime<FixedOffset>,
+}
+
+pub async fn verify_email(
+    State(users): State<DynUserRepository>,
+    State(user_tokens): State<DynUserTokenRepository>,
+    ValidatedJson(json): ValidatedJson<VerifyEmail>,
+) -> Result<Json<ResponseVerifyEmail>, AppError> {
+    confirm_email(&users, &user_tokens, &json.token).await
+}
+
+// the link in the mail
+pub async fn verify_email_link(
+    State(users): State<DynUserRepository>,
+    State(user_tokens): State<DynUserTokenRepository>,
+    ValidatedQuery(query): ValidatedQuery<VerifyEmail>,
+) -> Result<Json<ResponseVerifyEmail>, AppError> {
+    confirm_email(&users, &user_tokens, &query.token).await
+}
+
+async fn confirm_email(
+    users: &DynUserRepository,
+    user_tokens: &DynUserTokenRepository,
+    token: &str,
+) -> Result<Json<ResponseVerifyEmail>, AppError> {
+    let invalid = || AppError::validation("invalid or expired token");
+    let stored = user_tokens
+        .find_by_hash(UserTokenKind::VerifyEmail, &hash_token(token))
+        .await?;
+    let Some(stored) =
+        stored.filter(|stored| stored.used_at.is_none() && stored.expires_at > Utc::now())
+    else {
+        return Err(invalid());
+    };
+    let user = users.find_by_id(stored.user_id).await?;
+    // a token mailed to an earlier address doesn't verify the current one
+    let Some(mut user) =
+        user.filter(|user| user.deleted_at.is_none() && user.email == stored.email)
+    else {
+        return Err(invalid());
+    };
+    if !user_tokens.mark_used(stored.id).await? {
+        return Err(invalid());
+    }
+
+    let now: DateTime<FixedOffset> = Utc::now().into();
+    user.email_verified_at = Some(now);
+    let user = users.update(user).await?;
+    Ok(Json(ResponseVerifyEmail {
+        user_id: user.id,
+        email: user.email.unwrap_or_default(),
+        email_verified_at: now,
+    }))
+}
+
+// earlier tokens stay valid until they expire
+pub async fn resend_verification(
+    State(user_tokens): State<DynUserTokenRepository>,
+    State(mailer): State<DynMailer>,
+    State(config): State<Arc<Config>>,
+    Extension(user): Extension<UserModel>,
+) -> Result<String, AppError> {
+    if user.email_verified_at.is_some() {
+        return Err(AppError::Conflict("email is verified already".to_owned()));
+    }
+    send_verification(&user_tokens, &mailer, &config, &user).await?;
+    Ok("ok".to_owned())
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct ChangePassword {
+    pub old_password: String,
+    #[validate(custom = "validate_password")]
+    pub new_password: String,
+}
+
+/// What a new password ended, see `set_password`
+#[derive(Serialize, Debug)]
+pub struct ResponsePasswordSet {
+    /// Sessions revoked
+    pub revoked: u64,
+    pub api_keys_deleted: u64,
+}
+
+// every session ends, the current one too, so the client logs in again with the new password
+pub async fn change_password(
+    State(users): State<DynUserRepository>,
+    State(sessions): State<DynSessionRepository>,
+    State(user_tokens): State<DynUserTokenRepository>,
+    State(api_keys): State<DynApiKeyRepository>,
+    State(config): State<Arc<Config>>,
+    Extension(user): Extension<UserModel>,
+    ValidatedJson(json): ValidatedJson<ChangePassword>,
+) -> Result<Json<ResponsePasswordSet>, AppError> {
+    if !verify_password(json.old_password, &user.password)? {
+        return Err(AppError::Forbidden("old password is wrong".to_owned()));
+    }
+    let ended = set_password(
+        &users,
+        &sessions,
+        &user_tokens,
+        &api_keys,
+        &config,
+        user,
+        json.new_password,
+    )
+    .await?;
+    Ok(Json(ended))
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct ForgotPassword {
+    #[validate(email(message = "must be a valid email"))]
+    pub email: String,
+}
+
+// answers the same whether the address belongs to a user or not, so it can't be used to find
+// out who has an account. The mail goes out in the background, so the answer doesn't take longer
+// for an account either, and mail errors are only logged
+pub async fn forgot_password(
+    State(users): State<DynUserRepository>,
+    State(user_tokens): State<DynUserTokenRepository>,
+    State(mailer): State<DynMailer>,
+    State(config): State<Arc<Config>>,
+    State(shutdown): State<Shutdown>,
+    ValidatedJson(json): ValidatedJson<ForgotPassword>,
+) -> Result<String, AppError> {
+    let email = json.email.trim().to_lowercase();
+    let user = users.find_by_email(&email).await?;
+    if let Some(user) = user.filter(|user| user.deleted_at.is_none()) {
+        shutdown.spawn(move |_token| async move {
+            if let Err(err) =
+                send_password_reset(&user_tokens, &mailer, &config, &user, email).await
+            {
+                println!("forgot_password: reset mail not sent: {err:?}");
+            }
+        });
+    }
+    Ok("ok".to_owned())
+}
+
+async fn send_password_reset(
+    user_tokens: &DynUserTokenRepository,
+    mailer: &DynMailer,
+    config: &Config,
+    user: &UserModel,
+    email: String,
+) -> Result<(), AppError> {
+    let token = make_random_token();
+    let ttl = chrono::Duration::seconds(config.auth.password_reset_ttl.as_secs() as i64);
+    user_tokens
+        .create(NewUserToken {
+            user_id: user.id,
+            kind: UserTokenKind::ResetPassword,
+            token_hash: hash_token(&token),
+            email: Some(email.clone()),
+            expires_at: (Utc::now() + ttl).into(),
+        })
+        .await?;
+
+    // there is no page to pick the new password, the token goes to POST /users/reset-password
+    mailer
+        .send(Mail {
+            to: email,
+            subject: "Reset your password".to_owned(),
+            body: format!(
+                "Hi {},\n\nreset your password with\n\nPOST /users/reset-password {{\"token\": \"{token}\", \"new_password\": \"...\"}}\n\nThe token expires in {} minutes. If you didn't ask for this, ignore this mail.",
+                user.username,
+                ttl.num_minutes()
+            ),
+        })
+        .await
+        .map_err(|err| AppError::Upstream(format!("sending mail failed: {err}")))
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct ResetPassword {
+    pub token: String,
+    #[validate(custom = "validate_password")]
+    pub new_password: String,
+}
+
+pub async fn reset_password(
+    State(users): State<DynUserRepository>,
+    State(sessions): State<DynSessionRepository>,
+    State(user_tokens): State<DynUserTokenRepository>,
+    State(api_keys): State<DynApiKeyRepository>,
+    State(config): State<Arc<Config>>,
+    ValidatedJson(json): ValidatedJson<ResetPassword>,
+) -> Result<Json<ResponsePasswordSet>, AppError> {
+    let invalid = || AppError::validation("invalid or expired token");
+    let stored = user_tokens
+        .find_by_hash(UserTokenKind::ResetPassword, &hash_token(&json.token))
+        .await?;
+    let Some(stored) =
+        stored.filter(|stored| stored.used_at.is_none() && stored.expires_at > Utc::now())
+    else {
+        return Err(invalid());
+    };
+    let user = users.find_by_id(stored.user_id).await?;
+    let Some(user) = user.filter(|user| user.deleted_at.is_none()) else {
+        return Err(invalid());
+    };
+    if !user_tokens.mark_used(stored.id).await? {
+        return Err(invalid());
+    }
+
+    let ended = set_password(
+        &users,
+        &sessions,
+        &user_tokens,
+        &api_keys,
+        &config,
+        user,
+        json.new_password,
+    )
+    .await?;
+    Ok(Json(ended))
+}
+
+/// Store the new password, end every session of the user and delete the API keys, whoever knew
+/// the old password may have made some. Reset tokens that are still around stop working as well
+async fn set_password(
+    users: &DynUserRepository,
+    sessions: &DynSessionRepository,
+    user_tokens: &DynUserTokenRepository,
+    api_keys: &DynApiKeyRepository,
+    config: &Config,
+    mut user: UserModel,
+    password: String,
+) -> Result<ResponsePasswordSet, AppError> {
+    user.password = hash_password(password, config.auth.hash_cost)?;
+    let user = users.update(user).await?;
+    user_tokens
+        .use_all(user.id, UserTokenKind::ResetPassword)
+        .await?;
+    Ok(ResponsePasswordSet {
+        // refresh needs an active session, so the refresh tokens stop working too
+        revoked: sessions.revoke_all(user.id).await?,
+        api_keys_deleted: api_keys.delete_all(user.id).await?,
+    })
+}
@@ -0,0 +1,215 @@
+//! API keys for machine clients that can't log in. A key goes into the `X-API-Key` header instead
+//! of a bearer token, expires only if asked to, and is limited to the scopes it was created with.
+//! Keys look like `ak_<prefix>_<secret>`: the prefix is stored as is to look the key up and to
+//! tell keys apart in listings, the whole key only as its sha256.
+use axum::{extract::State, Extension, Json};
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use uuid::Uuid;
+use validator::{Validate, ValidationError};
+
+use super::sessions::LAST_SEEN_RESOLUTION_SECS;
+use crate::{
+    config::Config,
+    entities::{api_keys::Model as ApiKeyModel, users::Model as UserModel},
+    error::AppError,
+    rbac::{Permission, Role},
+    repository::{DynApiKeyRepository, DynUserRepository, NewApiKey},
+    utils::{hash_token, make_random_token, Claims},
+    validation::{ValidatedJson, ValidatedPath},
+};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+const PREFIX_LEN: usize = 12;
+
+/// Look up the key, check it and make up the claims `Require` checks: the role of the owner,
+/// limited to the scopes of the key
+pub async fn authenticate(
+    api_keys: &DynApiKeyRepository,
+    users: &DynUserRepository,
+    config: &Config,
+    key: &str,
+) -> Result<(UserModel, Claims), AppError> {
+    let invalid = || AppError::Unauthorized("invalid API key".to_owned());
+    let Some((prefix, _secret)) = key.strip_prefix("ak_").and_then(|key| key.split_once('_'))
+    else {
+        return Err(invalid());
+    };
+    let stored = api_keys.find_by_prefix(prefix).await?;
+    let Some(stored) = stored.filter(|stored| stored.key_hash == hash_token(key)) else {
+        return Err(invalid());
+    };
+    if stored
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= Utc::now())
+    {
+        return Err(AppError::Unauthorized("API key expired".to_owned()));
+    }
+    let user = users.find_by_id(stored.user_id).await?;
+    let Some(user) = user.filter(|user| user.deleted_at.is_none()) else {
+        return Err(invalid());
+    };
+
+    let claims = Claims {
+        sub: user.id.to_string(),
+        iss: config.auth.jwt_issuer.clone(),
+        aud: config.auth.jwt_audience.clone(),
+        jti: format!("ak_{}", stored.prefix),
+        sid: String::new(),
+        roles: vec![user.role.clone()],
+        scopes: Some(split_scopes(&stored.scopes)),
+        exp: stored
+            .expires_at
+            .map_or(0, |expires_at| expires_at.timestamp() as usize),
+        iat: Utc::now().timestamp() as usize,
+    };
+    touch_api_key(api_keys, stored).await?;
+    Ok((user, claims))
+}
+
+// like sessions, last_used_at is only written when it is older than the resolution
+async fn touch_api_key(
+    api_keys: &DynApiKeyRepository,
+    mut key: ApiKeyModel,
+) -> Result<(), AppError> {
+    let now = Utc::now();
+    let recent = key.last_used_at.is_some_and(|last_used_at| {
+        (now - last_used_at.with_timezone(&Utc)).num_seconds() < LAST_SEEN_RESOLUTION_SECS
+    });
+    if !recent {
+        key.last_used_at = Some(now.into());
+        api_keys.update(key).await?;
+    }
+    Ok(())
+}
+
+fn split_scopes(scopes: &str) -> Vec<String> {
+    scopes.split_whitespace().map(str::to_owned).collect()
+}
+
+fn validate_scopes(scopes: &[String]) -> Result<(), ValidationError> {
+    if let Some(scope) = scopes
+        .iter()
+        .find(|scope| scope.parse::<Permission>().is_err())
+    {
+        let names: Vec<&str> = Permission::ALL.iter().map(Permission::as_str).collect();
+        let mut error = ValidationError::new("scope");
+        error.message = Some(Cow::Owned(format!(
+            "unknown scope `{scope}`, use {}",
+            names.join(", ")
+        )));
+        return Err(error);
+    }
+    Ok(())
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct AddApiKey {
+    #[validate(length(min = 1, max = 64, message = "must have 1 to 64 characters"))]
+    pub name: String,
+    #[validate(
+        length(min = 1, message = "must name at least one permission"),
+        custom = "validate_scopes"
+    )]
+    pub scopes: Vec<String>,
+    /// Never expires when left out
+    #[validate(range(min = 1, max = 3650, message = "must be between 1 and 3650"))]
+    pub expires_in_days: Option<u32>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ResponseApiKey {
+    pub id: i32,
+    pub name: String,
+    /// `ak_<prefix>`, the start of the key
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<FixedOffset>,
+    pub expires_at: Option<DateTime<FixedOffset>>,
+    pub last_used_at: Option<DateTime<FixedOffset>>,
+}
+impl From<ApiKeyModel> for ResponseApiKey {
+    fn from(key: ApiKeyModel) -> Self {
+        Self {
+            id: key.id,
+            name: key.name,
+            prefix: format!("ak_{}", key.prefix),
+            scopes: split_scopes(&key.scopes),
+            created_at: key.created_at,
+            expires_at: key.expires_at,
+            last_used_at: key.last_used_at,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ResponseNewApiKey {
+    #[serde(flatten)]
+    pub api_key: ResponseApiKey,
+    /// The whole key, only ever shown in this response
+    pub key: String,
+}
+
+// a key can't be granted more than the role of its owner, and is checked against the role on
+// every request too, so a later role change limits the key as well
+pub async fn add_api_key(
+    State(api_keys): State<DynApiKeyRepository>,
+    Extension(user): Extension<UserModel>,
+    ValidatedJson(json): ValidatedJson<AddApiKey>,
+) -> Result<Json<ResponseNewApiKey>, AppError> {
+    let role = user.role.parse::<Role>().map_err(AppError::Internal)?;
+    // in the order of `Permission::ALL`, without duplicates
+    let scopes: Vec<Permission> = Permission::ALL
+        .into_iter()
+        .filter(|permission| json.scopes.iter().any(|scope| scope == permission.as_str()))
+        .collect();
+    if let Some(denied) = scopes.iter().find(|permission| !role.has(**permission)) {
+        return Err(AppError::Forbidden(format!(
+            "your role doesn't grant {denied}"
+        )));
+    }
+
+    let prefix = Uuid::new_v4().simple().to_string()[..PREFIX_LEN].to_owned();
+    let key = format!("ak_{prefix}_{}", make_random_token());
+    let expires_at = json.expires_in_days.map(|days| {
+        let expires_at = Utc::now() + chrono::Duration::days(i64::from(days));
+        expires_at.into()
+    });
+    let scopes: Vec<&str> = scopes.iter().map(Permission::as_str).collect();
+    let stored = api_keys
+        .create(NewApiKey {
+            user_id: user.id,
+            name: json.name,
+            prefix,
+            key_hash: hash_token(&key),
+            scopes: scopes.join(" "),
+            expires_at,
+        })
+        .await?;
+    Ok(Json(ResponseNewApiKey {
+        api_key: stored.into(),
+        key,
+    }))
+}
+
+pub async fn list_api_keys(
+    State(api_keys): State<DynApiKeyRepository>,
+    Extension(user): Extension<UserModel>,
+) -> Result<Json<Vec<ResponseApiKey>>, AppError> {
+    let keys = api_keys.list(user.id).await?;
+    Ok(Json(keys.into_iter().map(ResponseApiKey::from).collect()))
+}
+
+// keys of other users answer 404 as well
+pub async fn delete_api_key(
+    State(api_keys): State<DynApiKeyRepository>,
+    Extension(user): Extension<UserModel>,
+    ValidatedPath(key_id): ValidatedPath<i32>,
+) -> Result<String, AppError> {
+    let key = api_keys.find_by_id(key_id).await?;
+    if key.filter(|key| key.user_id == user.id).is_none() || !api_keys.delete(key_id).await? {
+        return Err(AppError::NotFound(format!("API key {key_id} not found")));
+    }
+    Ok("ok".to_owned())
+}
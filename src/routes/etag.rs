@@ -0,0 +1,119 @@
+//! Optimistic concurrency for tasks. Every update bumps the version of a task, which is sent as
+//! its `ETag`. A client that sends the ETag it read back in `If-Match` only changes the task when
+//! nobody else changed it in between, otherwise it gets 412 `precondition_failed`.
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    headers::{ETag, IfMatch},
+    http::{header::IF_MATCH, request::Parts},
+    TypedHeader,
+};
+use sea_orm::DbErr;
+
+use crate::error::AppError;
+
+pub fn etag(version: i32) -> ETag {
+    format!("\"{version}\"")
+        .parse()
+        .expect("a quoted number is a valid etag")
+}
+
+/// The `If-Match` header, None when the request has none. A malformed one answers 400
+pub struct IfMatchHeader(pub Option<IfMatch>);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for IfMatchHeader {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        // a missing header decodes as an empty list, which no version would match
+        if !parts.headers.contains_key(IF_MATCH) {
+            return Ok(Self(None));
+        }
+        let TypedHeader(if_match) = TypedHeader::<IfMatch>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| AppError::validation("invalid If-Match header"))?;
+        Ok(Self(Some(if_match)))
+    }
+}
+
+impl IfMatchHeader {
+    /// Passes without a header, so clients that don't send one keep working
+    pub fn check(&self, what: &str, version: i32) -> Result<(), AppError> {
+        match &self.0 {
+            Some(if_match) if !if_match.precondition_passes(&etag(version)) => Err(changed(what)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// An update or delete that found the row changed since it was read, see `TaskRepository::update`
+pub fn stale(err: DbErr, what: &str) -> AppError {
+    match err {
+        DbErr::RecordNotUpdated => changed(what),
+        err => err.into(),
+    }
+}
+
+fn changed(what: &str) -> AppError {
+    AppError::PreconditionFailed(format!("{what} was changed since, fetch it again"))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Request;
+
+    use super::*;
+
+    async fn if_match(value: Option<&str>) -> Result<IfMatchHeader, AppError> {
+        let mut request = Request::builder();
+        if let Some(value) = value {
+            request = request.header(IF_MATCH, value);
+        }
+        let (mut parts, _) = request.body(()).unwrap().into_parts();
+        IfMatchHeader::from_request_parts(&mut parts, &()).await
+    }
+
+    fn is_changed(result: Result<(), AppError>) -> bool {
+        matches!(result, Err(AppError::PreconditionFailed(_)))
+    }
+
+    #[tokio::test]
+    async fn matching_tag_passes() {
+        let header = if_match(Some("\"3\"")).await.unwrap();
+        assert!(header.check("task 1", 3).is_ok());
+        let header = if_match(Some("\"2\", \"3\"")).await.unwrap();
+        assert!(header.check("task 1", 3).is_ok());
+    }
+
+    #[tokio::test]
+    async fn stale_tag_fails() {
+        let header = if_match(Some("\"2\"")).await.unwrap();
+        assert!(is_changed(header.check("task 1", 3)));
+    }
+
+    #[tokio::test]
+    async fn any_tag_passes() {
+        let header = if_match(Some("*")).await.unwrap();
+        assert!(header.check("task 1", 3).is_ok());
+    }
+
+    #[tokio::test]
+    async fn missing_header_passes() {
+        let header = if_match(None).await.unwrap();
+        assert!(header.0.is_none());
+        assert!(header.check("task 1", 3).is_ok());
+    }
+
+    #[test]
+    fn stale_maps_only_record_not_updated() {
+        assert!(matches!(
+            stale(DbErr::RecordNotUpdated, "task 1"),
+            AppError::PreconditionFailed(detail) if detail == "task 1 was changed since, fetch it again"
+        ));
+        assert!(matches!(
+            stale(DbErr::Custom("boom".to_owned()), "task 1"),
+            AppError::Database(_)
+        ));
+    }
+}
@@ -0,0 +1,98 @@
+//! Probes for the orchestrator: `/healthz` answers as long as the process serves requests,
+//! `/readyz` also checks the database and, when enabled, the Ethereum RPC node.
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use ethers::providers::{Http, Middleware, Provider};
+use sea_orm::{ConnectionTrait, DatabaseConnection, Statement};
+use serde::Serialize;
+use std::{collections::BTreeMap, future::Future, sync::Arc, time::Duration};
+use tokio::time::Instant;
+
+use crate::config::Config;
+
+#[derive(Serialize, Debug)]
+pub struct Readiness {
+    pub status: &'static str,
+    pub checks: BTreeMap<&'static str, Check>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Check {
+    pub status: &'static str,
+    pub latency_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'static str>,
+}
+
+pub async fn healthz() -> impl IntoResponse {
+    Json(serde_json::json!({ "status": "ok" }))
+}
+
+pub async fn readyz(
+    State(db_conn): State<DatabaseConnection>,
+    State(config): State<Arc<Config>>,
+) -> impl IntoResponse {
+    let timeout = config.health.timeout;
+    let rpc_url = config
+        .chain
+        .as_ref()
+        .filter(|_chain| config.health.check_rpc)
+        .map(|chain| chain.rpc_url.as_str());
+    let rpc = async {
+        match rpc_url {
+            Some(rpc_url) => Some(probe("rpc", timeout, ping_rpc(rpc_url)).await),
+            None => None,
+        }
+    };
+    let (database, rpc) = tokio::join!(probe("database", timeout, ping_db(&db_conn)), rpc);
+
+    let mut checks = BTreeMap::from([("database", database)]);
+    if let Some(rpc) = rpc {
+        checks.insert("rpc", rpc);
+    }
+    let ready = checks.values().all(|check| check.error.is_none());
+    let (code, status) = if ready {
+        (StatusCode::OK, "ok")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "unavailable")
+    };
+    (code, Json(Readiness { status, checks }))
+}
+
+// the response is public, so error details (hosts, the infura key in the rpc url) only go to the log
+async fn probe<F>(name: &str, timeout: Duration, check: F) -> Check
+where
+    F: Future<Output = Result<(), String>>,
+{
+    let started = Instant::now();
+    let error = match tokio::time::timeout(timeout, check).await {
+        Ok(Ok(())) => None,
+        Ok(Err(err)) => {
+            println!("readyz: {name} check failed: {err}");
+            Some("unreachable")
+        }
+        Err(_elapsed) => Some("timed out"),
+    };
+    Check {
+        status: if error.is_none() { "ok" } else { "error" },
+        latency_ms: started.elapsed().as_millis(),
+        error,
+    }
+}
+
+async fn ping_db(db_conn: &DatabaseConnection) -> Result<(), String> {
+    let backend = db_conn.get_database_backend();
+    db_conn
+        .execute(Statement::from_string(backend, "SELECT 1".to_owned()))
+        .await
+        .map(drop)
+        .map_err(|err| err.to_string())
+}
+
+async fn ping_rpc(rpc_url: &str) -> Result<(), String> {
+    let provider = Provider::<Http>::try_from(rpc_url).map_err(|err| err.to_string())?;
+    provider
+        .get_chainid()
+        .await
+        .map(drop)
+        .map_err(|err| err.to_string())
+}
@@ -7,9 +7,33 @@ use axum::{
 };
 use route_func::*;
 use sea_orm::DatabaseConnection;
+use std::sync::Arc;
 use tower_http::cors::{Any, CorsLayer};
 
+use crate::{
+    config::Config,
+    mailer::DynMailer,
+    repository::{
+        orm::{
+            SeaOrmApiKeyRepository, SeaOrmRefreshTokenRepository, SeaOrmSessionRepository,
+            SeaOrmTaskRepository, SeaOrmUserRepository, SeaOrmUserTokenRepository,
+        },
+        DynApiKeyRepository, DynRefreshTokenRepository, DynSessionRepository, DynTaskRepository,
+        DynUserRepository, DynUserTokenRepository,
+    },
+    shutdown::Shutdown,
+    throttle::LoginThrottle,
+};
+
+mod account;
+mod api_keys;
+mod etag;
+mod health;
+mod pagination;
 mod route_func;
+mod sessions;
+#[cfg(test)]
+mod tests;
 
 // get_appstate: MUST have Clone macro!
 // to auto extract fields: cargo add axum -F macro, add FromRef in macros below, then see get_appstate_mode()
@@ -17,10 +41,44 @@ mod route_func;
 pub struct AppState {
     pub mode: String,
     pub db_conn: DatabaseConnection,
+    pub users: DynUserRepository,
+    pub tasks: DynTaskRepository,
+    pub sessions: DynSessionRepository,
+    pub refresh_tokens: DynRefreshTokenRepository,
+    pub user_tokens: DynUserTokenRepository,
+    pub api_keys: DynApiKeyRepository,
+    pub mailer: DynMailer,
+    pub login_throttle: Arc<LoginThrottle>,
+    pub config: Arc<Config>,
+    pub shutdown: Shutdown,
+}
+
+pub async fn create_routes(
+    config: Arc<Config>,
+    db_conn: DatabaseConnection,
+    mailer: DynMailer,
+    shutdown: Shutdown,
+) -> Router {
+    let mode = config.server.mode.clone();
+    let app_state = AppState {
+        users: Arc::new(SeaOrmUserRepository::new(db_conn.clone())),
+        tasks: Arc::new(SeaOrmTaskRepository::new(db_conn.clone())),
+        sessions: Arc::new(SeaOrmSessionRepository::new(db_conn.clone())),
+        refresh_tokens: Arc::new(SeaOrmRefreshTokenRepository::new(db_conn.clone())),
+        user_tokens: Arc::new(SeaOrmUserTokenRepository::new(db_conn.clone())),
+        api_keys: Arc::new(SeaOrmApiKeyRepository::new(db_conn.clone())),
+        mailer,
+        login_throttle: Arc::new(LoginThrottle::new(config.login.clone())),
+        db_conn,
+        mode,
+        config,
+        shutdown,
+    };
+    router(app_state)
 }
 
-pub async fn create_routes(mode: String, db_conn: DatabaseConnection) -> Router {
-    let app_state = AppState { db_conn, mode };
+// the routes on top of any repositories, the tests hand in the in-memory ones
+fn router(app_state: AppState) -> Router {
     //to intercept incoming calls from untrusted brower origins
     let cors = CorsLayer::new()
         // allow `GET` and `POST` when accessing the resource
@@ -33,15 +91,40 @@ pub async fn create_routes(mode: String, db_conn: DatabaseConnection) -> Router
     //logout must have auth to continue
     //move hello up to test jwt to avoid logout every single time
     //test jwt by waiting pass duration time
-    //route_layer can ONLY BE ONE!!
+    //route_layer only wraps the routes added above it: the account routes get require_session
+    //and auth, the rest below it only auth
     Router::new()
         .route("/users/logout", post(logout))
+        .route("/users/me/verify-email", post(account::resend_verification))
+        .route("/users/me/password", post(account::change_password))
+        .route("/users/me/sessions", get(sessions::list_sessions))
+        .route("/users/me/sessions", delete(sessions::delete_all_sessions))
+        .route("/users/me/sessions/:id", delete(sessions::delete_session))
+        .route("/users/me/api-keys", get(api_keys::list_api_keys))
+        .route("/users/me/api-keys", post(api_keys::add_api_key))
+        .route("/users/me/api-keys/:id", delete(api_keys::delete_api_key))
+        .route_layer(middleware::from_fn(sessions::require_session))
         .route("/add_task", post(add_task))
+        .route("/tasks", get(get_tasks_all))
+        .route("/tasks/trash", get(get_tasks_trash))
+        .route("/tasks/:id", get(get_task_by_id))
+        .route("/tasks/:id", put(replace_task))
+        .route("/tasks/:id", patch(update_partial_task))
+        .route("/tasks/:id", delete(delete_task))
+        .route("/tasks/:id/complete", post(complete_task))
+        .route("/tasks/:id/reopen", post(reopen_task))
+        .route("/tasks/:id/restore", post(restore_task))
+        .route("/eth_local_txn", post(eth_local_txn))
+        .route("/eth_deploy_contract", post(eth_deploy_contract))
+        .route("/eth_live_write", post(eth_live_write))
+        .route("/eth_send_ether", post(eth_send_ether))
         .route("/hello", get(hello))
         .route_layer(middleware::from_fn_with_state(app_state.clone(), auth))
         //.route("/get_custom_middleware", get(get_custom_middleware))
         //.route_layer(middleware::from_fn(set_custom_middleware))
         .route("/", get(root))
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
         .route("/html", get(send_html))
         .route("/get_body_string", post(get_body_string))
         .route("/user/:id", get(get_user_by_id))
@@ -55,16 +138,12 @@ pub async fn create_routes(mode: String, db_conn: DatabaseConnection) -> Router
         .route("/validate_struct_input", post(validate_struct_input))
         .route("/users", post(add_user))
         .route("/users/login", post(login))
-        .route("/tasks/:id", get(get_task_by_id))
-        .route("/tasks", get(get_tasks_all))
-        .route("/tasks/:id", put(replace_task))
-        .route("/tasks/:id", patch(update_partial_task))
-        .route("/tasks/:id", delete(delete_task))
-        .route("/eth_local_txn", post(eth_local_txn))
-        .route("/eth_deploy_contract", post(eth_deploy_contract))
+        .route("/users/refresh", post(refresh))
+        .route("/users/verify-email", post(account::verify_email))
+        .route("/users/verify-email", get(account::verify_email_link))
+        .route("/users/forgot-password", post(account::forgot_password))
+        .route("/users/reset-password", post(account::reset_password))
         .route("/eth_live_read", post(eth_live_read))
-        .route("/eth_live_write", post(eth_live_write))
-        .route("/eth_send_ether", post(eth_send_ether))
         .route("/chainlink_prices", get(chainlink_prices))
         .route("/make_keypair2", get(make_keypair2))
         .route("/run_thread", post(run_thread))
@@ -0,0 +1,264 @@
+//! Paging of `GET /tasks`. A page is picked either by `offset` or by `cursor`: the cursor is the
+//! `next_cursor` of the previous page and holds the sort key values of its last row, so the next
+//! page starts right after that row however many rows were added or removed in between.
+//! Cursors are opaque to clients, base64url of `{"sort": "priority,-id", "after": [...]}`.
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::borrow::Cow;
+use validator::ValidationError;
+
+use crate::{
+    entities::tasks::Model as TaskModel,
+    error::AppError,
+    repository::{SortValue, TaskSort, TaskSortField},
+};
+
+pub const DEFAULT_LIMIT: u64 = 50;
+pub const MAX_LIMIT: u64 = 200;
+
+/// One page of a list. `next_cursor` is None on the last page, `total` is only there when asked
+/// for with `total=true`
+#[derive(Serialize, Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Cursor {
+    sort: String,
+    after: Vec<Value>,
+}
+
+/// `priority,-id`: comma separated fields, `-` for descending. `id` is added as the last key
+/// when missing, so rows with equal keys still have a fixed order
+pub fn parse_sort(sort: Option<&str>) -> Result<Vec<TaskSort>, String> {
+    let mut keys: Vec<TaskSort> = Vec::new();
+    for key in sort.unwrap_or_default().split(',').map(str::trim) {
+        if key.is_empty() {
+            continue;
+        }
+        let (name, descending) = match key.strip_prefix('-') {
+            Some(name) => (name, true),
+            None => (key, false),
+        };
+        let field: TaskSortField = name.parse()?;
+        if keys.iter().any(|key| key.field == field) {
+            return Err(format!("sort field `{field}` is given twice"));
+        }
+        keys.push(TaskSort { field, descending });
+    }
+    if !keys.iter().any(|key| key.field == TaskSortField::Id) {
+        keys.push(TaskSort {
+            field: TaskSortField::Id,
+            descending: false,
+        });
+    }
+    Ok(keys)
+}
+
+pub fn validate_sort(sort: &str) -> Result<(), ValidationError> {
+    parse_sort(Some(sort)).map(|_| ()).map_err(|message| {
+        let names: Vec<&str> = TaskSortField::ALL
+            .iter()
+            .map(TaskSortField::as_str)
+            .collect();
+        let mut error = ValidationError::new("sort");
+        error.message = Some(Cow::Owned(format!(
+            "{message}, sort by {}",
+            names.join(", ")
+        )));
+        error
+    })
+}
+
+fn sort_to_string(sort: &[TaskSort]) -> String {
+    let keys: Vec<String> = sort
+        .iter()
+        .map(|key| {
+            if key.descending {
+                format!("-{}", key.field)
+            } else {
+                key.field.to_string()
+            }
+        })
+        .collect();
+    keys.join(",")
+}
+
+/// The cursor of the page that starts after this task
+pub fn encode_cursor(sort: &[TaskSort], task: &TaskModel) -> String {
+    let after = sort
+        .iter()
+        .map(|key| match key.field.value_of(task) {
+            SortValue::Int(value) => Value::from(value),
+            SortValue::Text(value) => value.map_or(Value::Null, Value::from),
+            SortValue::Time(value) => {
+                value.map_or(Value::Null, |value| Value::from(value.to_rfc3339()))
+            }
+        })
+        .collect();
+    let cursor = Cursor {
+        sort: sort_to_string(sort),
+        after,
+    };
+    URL_SAFE_NO_PAD.encode(serde_json::to_vec(&cursor).unwrap_or_default())
+}
+
+/// The sort key values a cursor holds. A cursor only works with the sort it was made for
+pub fn decode_cursor(sort: &[TaskSort], cursor: &str) -> Result<Vec<SortValue>, AppError> {
+    let invalid = || AppError::validation("invalid cursor");
+    let bytes = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| invalid())?;
+    let cursor: Cursor = serde_json::from_slice(&bytes).map_err(|_| invalid())?;
+    if cursor.sort != sort_to_string(sort) {
+        return Err(AppError::validation(
+            "the cursor was made for another sort, keep the sort while paging",
+        ));
+    }
+    if cursor.after.len() != sort.len() {
+        return Err(invalid());
+    }
+    sort.iter()
+        .zip(cursor.after)
+        .map(|(key, value)| {
+            let value = match (key.field, value) {
+                (TaskSortField::Id, Value::Number(value)) => value
+                    .as_i64()
+                    .and_then(|value| i32::try_from(value).ok())
+                    .map(SortValue::Int),
+                (TaskSortField::Title | TaskSortField::Priority, Value::Null) => {
+                    Some(SortValue::Text(None))
+                }
+                (TaskSortField::Title | TaskSortField::Priority, Value::String(value)) => {
+                    Some(SortValue::Text(Some(value)))
+                }
+                (
+                    TaskSortField::CreatedAt
+                    | TaskSortField::CompletedAt
+                    | TaskSortField::DeletedAt,
+                    Value::Null,
+                ) => Some(SortValue::Time(None)),
+                (
+                    TaskSortField::CreatedAt
+                    | TaskSortField::CompletedAt
+                    | TaskSortField::DeletedAt,
+                    Value::String(value),
+                ) => DateTime::parse_from_rfc3339(&value)
+                    .ok()
+                    .map(|value| SortValue::Time(Some(value))),
+                _ => None,
+            };
+            value.ok_or_else(invalid)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task() -> TaskModel {
+        TaskModel {
+            id: 7,
+            priority: Some("A".to_owned()),
+            title: "invoice".to_owned(),
+            completed_at: None,
+            description: None,
+            deleted_at: None,
+            user_id: Some(1),
+            is_default: None,
+            created_at: DateTime::parse_from_rfc3339("2026-01-02T03:04:05.678+00:00").ok(),
+            version: 1,
+        }
+    }
+
+    fn sort(sort: &str) -> Vec<TaskSort> {
+        parse_sort(Some(sort)).unwrap()
+    }
+
+    fn detail(result: Result<Vec<SortValue>, AppError>) -> String {
+        match result {
+            Err(AppError::Validation { detail, .. }) => detail,
+            other => panic!("expected a validation error, got {other:?}"),
+        }
+    }
+
+    fn encode_raw(json: &str) -> String {
+        URL_SAFE_NO_PAD.encode(json)
+    }
+
+    #[test]
+    fn parse_sort_appends_id() {
+        assert_eq!(sort_to_string(&sort("-priority")), "-priority,id");
+        assert_eq!(sort_to_string(&sort("")), "id");
+        assert_eq!(sort_to_string(&sort("-id,title")), "-id,title");
+    }
+
+    #[test]
+    fn parse_sort_rejects_unknown_and_duplicate_fields() {
+        assert!(parse_sort(Some("color")).is_err());
+        assert!(parse_sort(Some("title,-title")).is_err());
+    }
+
+    #[test]
+    fn cursor_round_trip() {
+        let sort = sort("-priority,created_at,title");
+        let task = task();
+        let after = decode_cursor(&sort, &encode_cursor(&sort, &task)).unwrap();
+        let expected: Vec<SortValue> = sort.iter().map(|key| key.field.value_of(&task)).collect();
+        assert_eq!(after, expected);
+    }
+
+    #[test]
+    fn cursor_round_trip_with_nulls() {
+        let sort = sort("priority,completed_at,-deleted_at");
+        let task = TaskModel {
+            priority: None,
+            ..task()
+        };
+        let after = decode_cursor(&sort, &encode_cursor(&sort, &task)).unwrap();
+        assert_eq!(
+            after,
+            vec![
+                SortValue::Text(None),
+                SortValue::Time(None),
+                SortValue::Time(None),
+                SortValue::Int(7),
+            ]
+        );
+    }
+
+    #[test]
+    fn cursor_of_another_sort_is_rejected() {
+        let cursor = encode_cursor(&sort("priority"), &task());
+        assert!(detail(decode_cursor(&sort("-priority"), &cursor)).contains("another sort"));
+        assert!(detail(decode_cursor(&sort("title"), &cursor)).contains("another sort"));
+    }
+
+    #[test]
+    fn tampered_cursor_is_rejected() {
+        let sort = sort("priority");
+        let cursors = [
+            "not base64!".to_owned(),
+            encode_raw("not json"),
+            // too few and too many values
+            encode_raw(r#"{"sort":"priority,id","after":["A"]}"#),
+            encode_raw(r#"{"sort":"priority,id","after":["A",7,8]}"#),
+            // values of the wrong type or out of range
+            encode_raw(r#"{"sort":"priority,id","after":["A","7"]}"#),
+            encode_raw(r#"{"sort":"priority,id","after":[1,7]}"#),
+            encode_raw(r#"{"sort":"priority,id","after":["A",4294967296]}"#),
+            encode_raw(r#"{"sort":"priority,id","after":["A",null]}"#),
+        ];
+        for cursor in cursors {
+            assert_eq!(detail(decode_cursor(&sort, &cursor)), "invalid cursor");
+        }
+        let sort = parse_sort(Some("created_at")).unwrap();
+        let cursor = encode_raw(r#"{"sort":"created_at,id","after":["yesterday",7]}"#);
+        assert_eq!(detail(decode_cursor(&sort, &cursor)), "invalid cursor");
+    }
+}
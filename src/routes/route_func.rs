@@ -1,32 +1,53 @@
 use axum::{
-    async_trait,
-    body::HttpBody,
-    extract::{FromRequest, Path, Query, State},
-    headers::{authorization::Bearer, Authorization, UserAgent},
+    extract::{Path, Query, State},
+    headers::{authorization::Bearer, Authorization, ETag, UserAgent},
     http::{HeaderMap, Request, StatusCode},
     middleware::Next,
     response::Response,
     response::{Html, IntoResponse},
-    BoxError, Extension, Json, RequestExt, TypedHeader,
-};
-use chrono::{DateTime, FixedOffset};
-use sea_orm::{
-    prelude::DateTimeWithTimeZone, ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection,
-    EntityTrait, IntoActiveModel, QueryFilter, Set,
+    Extension, Json, TypedHeader,
 };
+use chrono::{DateTime, FixedOffset, Utc};
+use sea_orm::{prelude::DateTimeWithTimeZone, DatabaseConnection};
 use serde::{Deserialize, Serialize};
-use validator::Validate;
+use validator::{Validate, ValidationError};
 
-use super::AppState;
+use super::{
+    account::send_verification,
+    api_keys,
+    etag::{etag, stale, IfMatchHeader},
+    pagination::{
+        decode_cursor, encode_cursor, parse_sort, validate_sort, Page, DEFAULT_LIMIT, MAX_LIMIT,
+    },
+    sessions::{
+        extend_session, is_active, revoke_session, start_session, touch_session, ClientInfo,
+    },
+    AppState,
+};
 use crate::blockchain::{contract_deploy::*, simple_txn_live::*, simple_txn_local::*};
 use crate::{
+    config::{ChainConfig, Config},
     entities::{
-        tasks::{self, Entity as Tasks},
-        users::{self, Entity as Users, Model as UserModel},
+        sessions::Model as SessionModel, tasks::Model as TaskModel, users::Model as UserModel,
+    },
+    error::{is_unique_violation, AppError},
+    mailer::DynMailer,
+    rbac::{self, EthWrite, Permission, Require, Role, TasksRead, TasksWrite},
+    repository::{
+        DynApiKeyRepository, DynRefreshTokenRepository, DynSessionRepository, DynTaskRepository,
+        DynUserRepository, DynUserTokenRepository, NewTask, NewUser, TaskFilter, TaskPage,
+        TaskSearch,
+    },
+    shutdown::Shutdown,
+    throttle::LoginThrottle,
+    utils::{
+        dummy_verify_password, hash_password, hash_token, verify_jwt, verify_password, Claims,
+    },
+    validation::{
+        validate_password, validate_username, ValidatedJson, ValidatedPath, ValidatedQuery,
     },
-    utils::{hash_password, make_jwt, verify_jwt, verify_password, AppError},
 };
-use std::{collections::HashMap, time::Duration};
+use std::{borrow::Cow, collections::HashMap, sync::Arc, time::Duration};
 use std::{sync::mpsc, thread};
 /*use sqlx::MySqlPool;
 // basic handler that responds with a static string
@@ -53,7 +74,7 @@ pub async fn get_body_string(body: String) -> String {
 pub async fn exactmatch() -> impl IntoResponse {
     Json(User {
         username: "exactmatch will take priority".to_owned(),
-        user_id: 092,
+        user_id: 92,
     })
 }
 
@@ -83,6 +104,7 @@ pub async fn query_headers(TypedHeader(user_agent): TypedHeader<UserAgent>) -> S
     user_agent.to_string()
 }
 
+#[allow(dead_code)]
 #[derive(Clone)] //ADD Clone to avoid trait bound error
 pub struct SecurityLevel(pub String);
 //add "pub" inside OR "cannot initialize a tuple struct which contains private field"
@@ -116,13 +138,33 @@ pub async fn query_custom_headers(headers: HeaderMap) -> String {
     auth_headervalue.to_str().expect("err2").to_owned()
 }
 //put your extractor after State(db_conn)
+// a bearer token wins over an API key when a request has both
 pub async fn auth<T>(
-    State(db_conn): State<DatabaseConnection>,
-    TypedHeader(token): TypedHeader<Authorization<Bearer>>,
+    State(users): State<DynUserRepository>,
+    State(sessions): State<DynSessionRepository>,
+    State(api_keys): State<DynApiKeyRepository>,
+    State(config): State<Arc<Config>>,
+    token: Option<TypedHeader<Authorization<Bearer>>>,
     mut request: Request<T>,
     next: Next<T>,
 ) -> Result<Response, AppError> {
     println!("auth");
+    let Some(TypedHeader(token)) = token else {
+        let api_key = request
+            .headers()
+            .get(api_keys::API_KEY_HEADER)
+            .and_then(|value| value.to_str().ok());
+        let Some(api_key) = api_key else {
+            return Err(AppError::Unauthorized(
+                "missing bearer token or API key".to_owned(),
+            ));
+        };
+        // no session: the account routes are closed to API keys by `require_session`
+        let (user, claims) = api_keys::authenticate(&api_keys, &users, &config, api_key).await?;
+        request.extensions_mut().insert(user);
+        request.extensions_mut().insert(claims);
+        return Ok(next.run(request).await);
+    };
     let token = token.token().to_owned();
     // let token = request
     //     .headers()
@@ -133,7 +175,6 @@ pub async fn auth<T>(
     //     })?
     //     .token()
     //     .to_owned();
-    dbg!(&token);
 
     // let db_conn = request
     //     .extensions()
@@ -144,23 +185,28 @@ pub async fn auth<T>(
     //     })?;
     println!("db connected");
 
-    let user = Users::find()
-        .filter(users::Column::Token.eq(Some(token.clone())))
-        .one(&db_conn)
-        .await
-        .map_err(|err| {
-            println!("error 103: {err}");
-            AppError::new(StatusCode::INTERNAL_SERVER_ERROR, "error 103")
-        })?;
-    println!("user option is found");
-    let Some(user) = user else {
-        return Err(AppError::new(StatusCode::UNAUTHORIZED, "unauthorized. login or sign up. error 104"))
+    // the signature proves who the token belongs to, so the user is loaded by id
+    let claims = verify_jwt(&token, &config.auth)?;
+    println!("token is valid");
+    let user_id = claims.user_id()?;
+    // the token is only good as long as its session, logout and revoking end it early
+    let session = sessions.find_by_id(&claims.sid).await?;
+    let Some(session) = session.filter(|session| session.user_id == user_id && is_active(session))
+    else {
+        return Err(AppError::Unauthorized("session revoked".to_owned()));
+    };
+    let user = users.find_by_id(user_id).await?;
+    let Some(user) = user.filter(|user| user.deleted_at.is_none()) else {
+        return Err(AppError::Unauthorized(
+            "unauthorized. login or sign up".to_owned(),
+        ));
     };
     println!("user is valid");
-    verify_jwt(&token)?; //move jwt verification here to confuse hackers so they don't know what is wrong out of jwt, db, or user
-    println!("token is valid");
+    let session = touch_session(&sessions, session).await?;
 
     request.extensions_mut().insert(user);
+    request.extensions_mut().insert(session);
+    request.extensions_mut().insert(claims);
     Ok(next.run(request).await)
 }
 //2xx: all ok. 201 success for created item
@@ -173,143 +219,209 @@ pub async fn auth<T>(
 //https://github.com/Keats/validator
 #[derive(Deserialize, Debug, Validate)]
 pub struct AddUser {
+    #[validate(custom = "validate_username")]
     pub username: String,
-    #[validate(length(min = 8, message = "must have at least 8 characters"))]
+    #[validate(custom = "validate_password")]
     pub password: String,
     #[validate(email(message = "must be a valid email"))]
     pub email: String,
     //pub legalname: Option<String>,
 } //Option field in input struct
-  //Custom Extractor to validate struct input
+  //ValidatedJson<AddUser> runs the rules above before the handler
   //https://github.com/Keats/validator
 #[derive(Serialize, Debug)]
 pub struct ResponseAddUser {
     pub user_id: i32,
     pub username: String,
-    pub email: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
     pub token: Option<String>,
+    pub refresh_token: Option<String>,
     //pub deleted_at: Option<DateTime<FixedOffset>>,
 }
 
-//https://docs.rs/axum/latest/axum/extract/trait.FromRequest.html
-#[async_trait]
-impl<S, B> FromRequest<S, B> for AddUser
-where
-    B: HttpBody + Send + 'static,
-    B::Data: Send,
-    B::Error: Into<BoxError>,
-    S: Send + Sync,
-{
-    type Rejection = (StatusCode, String);
-
-    async fn from_request(request: Request<B>, _state: &S) -> Result<Self, Self::Rejection> {
-        println!("----------== from_request");
-        let Json(user) = request
-            .extract::<Json<AddUser>, _>()
-            .await
-            .map_err(|error| (StatusCode::BAD_REQUEST, format!("{error}")))?;
-
-        if let Err(errors) = user.validate() {
-            return Err((StatusCode::BAD_REQUEST, format!("{errors}")));
-        }
-        Ok(user)
-    }
-}
-
-pub async fn validate_struct_input(json: AddUser) -> impl IntoResponse {
-    dbg!(json);
+pub async fn validate_struct_input(
+    ValidatedJson(_json): ValidatedJson<AddUser>,
+) -> impl IntoResponse {
     (StatusCode::CREATED, "new user added".to_owned()).into_response()
 }
 
+#[allow(clippy::too_many_arguments)] // one extractor per repository
 pub async fn add_user(
-    State(db_conn): State<DatabaseConnection>,
-    //json: AddUser, //Must use this json format for validation!
-    Json(json): Json<AddUser>,
-) -> Result<Json<ResponseAddUser>, StatusCode> {
-    dbg!(&json);
-    let jwt_token = make_jwt()?;
-    let new_user = users::ActiveModel {
-        username: Set(json.username),
-        password: Set(hash_password(json.password)?),
-        token: Set(Some(jwt_token)),
-        ..Default::default()
+    State(users): State<DynUserRepository>,
+    State(sessions): State<DynSessionRepository>,
+    State(refresh_tokens): State<DynRefreshTokenRepository>,
+    State(user_tokens): State<DynUserTokenRepository>,
+    State(mailer): State<DynMailer>,
+    State(config): State<Arc<Config>>,
+    client: ClientInfo,
+    ValidatedJson(json): ValidatedJson<AddUser>,
+) -> Result<Json<ResponseAddUser>, AppError> {
+    if users.find_by_username(&json.username).await?.is_some() {
+        return Err(AppError::Conflict(format!(
+            "username {} is taken",
+            json.username
+        )));
+    }
+    let email = json.email.trim().to_lowercase();
+    if users.find_by_email(&email).await?.is_some() {
+        return Err(AppError::Conflict(format!("email {email} is taken")));
+    }
+    let username = json.username.clone();
+    let new_user = users
+        .create(NewUser {
+            username: json.username,
+            password: hash_password(json.password, config.auth.hash_cost)?,
+            role: Role::User.to_string(),
+            email: Some(email.clone()),
+        })
+        .await;
+    // a signup with the same name or email can get in between the checks above and the insert
+    let new_user = match new_user {
+        Err(err) if is_unique_violation(&err) => {
+            return Err(match users.find_by_email(&email).await? {
+                Some(_) => AppError::Conflict(format!("email {email} is taken")),
+                None => AppError::Conflict(format!("username {username} is taken")),
+            });
+        }
+        new_user => new_user?,
+    };
+    // the account works without a verified email, a failed mail can be resent later
+    if let Err(err) = send_verification(&user_tokens, &mailer, &config, &new_user).await {
+        println!("add_user: verification mail not sent: {err:?}");
     }
-    .save(&db_conn)
-    .await
-    .map_err(|err| {
-        println!("saving new user failed:");
-        dbg!(err);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-    dbg!(&new_user);
+    let tokens = start_session(&sessions, &refresh_tokens, &config, &new_user, client).await?;
     Ok(Json(ResponseAddUser {
-        user_id: new_user.id.unwrap(),
-        username: new_user.username.unwrap(),
-        email: json.email.to_owned(),
-        token: new_user.token.unwrap(),
+        user_id: new_user.id,
+        username: new_user.username,
+        email: new_user.email,
+        email_verified: false,
+        token: Some(tokens.token),
+        refresh_token: Some(tokens.refresh_token),
     }))
 }
 // State(..) will check if ".with_state(..) is in the routes"
+// no username or password rules here, accounts from before them have to be able to log in
 #[derive(Deserialize, Debug, Validate)]
 pub struct Login {
+    #[validate(length(min = 1, message = "must not be empty"))]
     pub username: String,
-    #[validate(length(min = 8, message = "must have at least 8 characters"))]
+    #[validate(length(min = 1, message = "must not be empty"))]
     pub password: String,
 } //Option field in input struct
+  // a missing user and a wrong password answer the same and take the same time, so login can't be
+  // used to find out which usernames exist
 pub async fn login(
-    State(db_conn): State<DatabaseConnection>,
-    Json(json): Json<Login>,
-) -> Result<Json<ResponseAddUser>, StatusCode> {
-    dbg!(&json);
-    let db_user = Users::find()
-        .filter(users::Column::Username.eq(json.username))
-        .one(&db_conn)
-        .await
-        .map_err(|err| {
-            println!("finding user failed:");
-            dbg!(err);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
-    dbg!(&db_user);
-
-    if let Some(db_user) = db_user {
-        if !verify_password(json.password, &db_user.password)? {
-            return Err(StatusCode::UNAUTHORIZED);
+    State(users): State<DynUserRepository>,
+    State(sessions): State<DynSessionRepository>,
+    State(refresh_tokens): State<DynRefreshTokenRepository>,
+    State(login_throttle): State<Arc<LoginThrottle>>,
+    State(config): State<Arc<Config>>,
+    client: ClientInfo,
+    ValidatedJson(json): ValidatedJson<Login>,
+) -> Result<Json<ResponseAddUser>, AppError> {
+    let ip = client.ip.clone();
+    if let Some(wait) = login_throttle.check(&json.username, ip.as_deref()) {
+        // round up, a client retrying after `Retry-After` must not still be locked out
+        return Err(AppError::TooManyRequests(
+            wait.as_secs() + u64::from(wait.subsec_nanos() > 0),
+        ));
+    }
+    let db_user = users.find_by_username(&json.username).await?;
+    let db_user = db_user.filter(|db_user| db_user.deleted_at.is_none());
+    let verified = match &db_user {
+        Some(db_user) => verify_password(json.password, &db_user.password)?,
+        None => {
+            dummy_verify_password(json.password, config.auth.hash_cost)?;
+            false
         }
+    };
+    let Some(db_user) = db_user.filter(|_| verified) else {
+        login_throttle.record_failure(&json.username, ip.as_deref());
+        return Err(AppError::Unauthorized(
+            "wrong username or password".to_owned(),
+        ));
+    };
+    login_throttle.record_success(&db_user.username);
 
-        let jwt_token = make_jwt()?;
-        println!("new jwt_token:{jwt_token}");
-        let mut user = db_user.into_active_model();
-        user.token = Set(Some(jwt_token));
+    // a new session per login, earlier ones stay valid so several devices can be logged in
+    let tokens = start_session(&sessions, &refresh_tokens, &config, &db_user, client).await?;
 
-        let saved_user = user
-            .save(&db_conn)
-            .await
-            .map_err(|_e| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-        Ok(Json(ResponseAddUser {
-            user_id: saved_user.id.unwrap(),
-            username: saved_user.username.unwrap(),
-            email: "xyz@gmail.com".to_owned(),
-            token: saved_user.token.unwrap(),
-        }))
-    } else {
-        Err(StatusCode::NOT_FOUND)
+    Ok(Json(ResponseAddUser {
+        user_id: db_user.id,
+        username: db_user.username,
+        email: db_user.email,
+        email_verified: db_user.email_verified_at.is_some(),
+        token: Some(tokens.token),
+        refresh_token: Some(tokens.refresh_token),
+    }))
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct RefreshToken {
+    pub refresh_token: String,
+}
+#[derive(Serialize, Debug)]
+pub struct ResponseRefresh {
+    pub user_id: i32,
+    pub token: String,
+    pub refresh_token: String,
+}
+// every refresh token works once: it is swapped for a new access token and a new refresh token
+pub async fn refresh(
+    State(users): State<DynUserRepository>,
+    State(sessions): State<DynSessionRepository>,
+    State(refresh_tokens): State<DynRefreshTokenRepository>,
+    State(config): State<Arc<Config>>,
+    ValidatedJson(json): ValidatedJson<RefreshToken>,
+) -> Result<Json<ResponseRefresh>, AppError> {
+    let stored = refresh_tokens
+        .find_by_hash(&hash_token(&json.refresh_token))
+        .await?;
+    let Some(stored) = stored.filter(|stored| stored.revoked_at.is_none()) else {
+        return Err(AppError::Unauthorized("invalid refresh token".to_owned()));
+    };
+    let session = sessions.find_by_id(&stored.family_id).await?;
+    let Some(session) = session.filter(|session| session.revoked_at.is_none()) else {
+        return Err(AppError::Unauthorized("session revoked".to_owned()));
+    };
+    if stored.expires_at <= Utc::now() {
+        return Err(AppError::SessionExpired);
     }
+    // a used token coming back means it was stolen, or the client lost the race with a thief.
+    // Either way nothing of that session can be trusted anymore
+    if stored.used_at.is_some() || !refresh_tokens.mark_used(stored.id).await? {
+        println!("refresh: token reuse, revoking session {}", session.id);
+        revoke_session(&sessions, &refresh_tokens, session).await?;
+        return Err(AppError::Unauthorized(
+            "refresh token reuse detected, log in again".to_owned(),
+        ));
+    }
+
+    let user = users.find_by_id(stored.user_id).await?;
+    let Some(user) = user.filter(|user| user.deleted_at.is_none()) else {
+        return Err(AppError::Unauthorized(
+            "unauthorized. login or sign up".to_owned(),
+        ));
+    };
+    // only extends a session that is still live, a revoke from a concurrent reuse wins
+    let tokens = extend_session(&sessions, &refresh_tokens, &config, &user, session).await?;
+    Ok(Json(ResponseRefresh {
+        user_id: user.id,
+        token: tokens.token,
+        refresh_token: tokens.refresh_token,
+    }))
 }
+
+// revokes the session of the token, its refresh tokens stop working. The access token itself is
+// rejected from now on as well, because auth checks the session
 pub async fn logout(
-    State(db_conn): State<DatabaseConnection>,
     //TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
-    Extension(user): Extension<UserModel>,
-) -> Result<(), StatusCode> {
-    let mut user = user.into_active_model();
-    dbg!(&user);
-    user.token = Set(None);
-    let _saved_user = user
-        .save(&db_conn)
-        .await
-        .map_err(|_e| StatusCode::INTERNAL_SERVER_ERROR)?;
-    Ok(())
+    State(sessions): State<DynSessionRepository>,
+    State(refresh_tokens): State<DynRefreshTokenRepository>,
+    Extension(session): Extension<SessionModel>,
+) -> Result<(), AppError> {
+    revoke_session(&sessions, &refresh_tokens, session).await
 }
 
 // Serialize for output json
@@ -334,87 +446,258 @@ pub struct ResponseTask {
     pub description: Option<String>,
     pub deleted_at: Option<DateTime<FixedOffset>>,
     pub user_id: Option<i32>,
+    pub created_at: Option<DateTime<FixedOffset>>,
+    pub completed_at: Option<DateTime<FixedOffset>>,
+    pub is_default: Option<bool>,
+    /// Bumped by every change, the `ETag` of the task
+    pub version: i32,
 } //Find the field types from task: Option<Model>, then put them into the type fields inside this Output struct above; THEN add chronos with serde feature to serialize the output!
 
+impl From<TaskModel> for ResponseTask {
+    fn from(task: TaskModel) -> Self {
+        Self {
+            task_id: task.id,
+            title: task.title,
+            priority: task.priority,
+            description: task.description,
+            deleted_at: task.deleted_at,
+            user_id: task.user_id,
+            created_at: task.created_at,
+            completed_at: task.completed_at,
+            is_default: task.is_default,
+            version: task.version,
+        }
+    }
+}
+
+/// A single task with its version as `ETag`
+pub type TaskResponse = (TypedHeader<ETag>, Json<ResponseTask>);
+fn task_response(task: TaskModel) -> TaskResponse {
+    (TypedHeader(etag(task.version)), Json(task.into()))
+}
+
+// the owner whose tasks a request may touch, None for admins who may touch every task
+fn task_owner(user: &UserModel, claims: &Claims) -> Option<i32> {
+    if rbac::granted(claims, Permission::TasksAdmin) {
+        None
+    } else {
+        Some(user.id)
+    }
+}
+// tasks of other users answer 404 like missing ones, so their ids can't be probed
+async fn find_task(
+    tasks: &DynTaskRepository,
+    owner: Option<i32>,
+    task_id: i32,
+) -> Result<TaskModel, AppError> {
+    let task = tasks.find_by_id(task_id).await?;
+    match task.filter(|task| owner.is_none() || task.user_id == owner) {
+        Some(task) => Ok(task),
+        None => Err(AppError::NotFound(format!("task {task_id} not found"))),
+    }
+}
+
+/// Like `find_task`, but a task in the trash is not found either. Only restore brings it back
+async fn find_live_task(
+    tasks: &DynTaskRepository,
+    owner: Option<i32>,
+    task_id: i32,
+) -> Result<TaskModel, AppError> {
+    let task = find_task(tasks, owner, task_id).await?;
+    if task.deleted_at.is_some() {
+        return Err(AppError::NotFound(format!("task {task_id} not found")));
+    }
+    Ok(task)
+}
+
 //curl localhost:3000/user/9
 pub async fn get_task_by_id(
-    State(db_conn): State<DatabaseConnection>,
-    Path(task_id): Path<i32>,
-) -> Result<Json<ResponseTask>, StatusCode> {
-    let task = Tasks::find_by_id(task_id)
-        .filter(tasks::Column::DeletedAt.is_null())
-        .one(&db_conn)
-        .await
-        .map_err(|_err| StatusCode::INTERNAL_SERVER_ERROR)?;
+    _: Require<TasksRead>,
+    State(tasks): State<DynTaskRepository>,
+    Extension(user): Extension<UserModel>,
+    Extension(claims): Extension<Claims>,
+    ValidatedPath(task_id): ValidatedPath<i32>,
+) -> Result<TaskResponse, AppError> {
+    let task = Some(find_task(&tasks, task_owner(&user, &claims), task_id).await?)
+        .filter(|task| task.deleted_at.is_none());
 
     dbg!(&task);
 
     if let Some(task) = task {
-        Ok(Json(ResponseTask {
-            task_id,
-            title: task.title,
-            priority: task.priority,
-            description: task.description,
-            deleted_at: task.deleted_at,
-            user_id: task.user_id,
-        }))
+        Ok(task_response(task))
     } else {
-        Err(StatusCode::NOT_FOUND)
+        Err(AppError::NotFound(format!("task {task_id} not found")))
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchMode {
+    #[default]
+    Substring,
+    /// Postgres only, substring search on other databases
+    FullText,
+}
+#[derive(Deserialize, Validate)]
 pub struct GetTasksParams {
-    pub task_id: Option<i32>,
+    /// `3,7`: any of these ids
+    #[validate(custom = "validate_task_ids")]
+    pub task_id: Option<String>,
     pub title: Option<String>,
+    /// `A,B`: any of these priorities, an empty one for none
     pub priority: Option<String>,
+    pub completed: Option<bool>,
+    /// Tasks of this user id, other users than the caller need `tasks:admin`
+    pub owner: Option<i32>,
+    pub is_default: Option<bool>,
+    /// Ranges include `from` and exclude `to`
+    pub created_from: Option<DateTime<FixedOffset>>,
+    pub created_to: Option<DateTime<FixedOffset>>,
+    pub completed_from: Option<DateTime<FixedOffset>>,
+    pub completed_to: Option<DateTime<FixedOffset>>,
+    /// Text to look for in title and description
+    #[validate(length(min = 1, max = 200, message = "must have 1 to 200 characters"))]
+    pub q: Option<String>,
+    /// How `q` is matched
+    #[serde(default)]
+    pub search: SearchMode,
+    /// Page size, `DEFAULT_LIMIT` when left out
+    #[validate(range(min = 1, max = 200, message = "must be between 1 and 200"))]
+    pub limit: Option<u64>,
+    /// `next_cursor` of the previous page
+    pub cursor: Option<String>,
+    pub offset: Option<u64>,
+    /// `priority,-id`, by id when left out
+    #[validate(custom = "validate_sort")]
+    pub sort: Option<String>,
+    /// Count every matching task into `total`, it costs a second query
+    #[serde(default)]
+    pub total: bool,
+}
+fn parse_task_ids(ids: &str) -> Result<Vec<i32>, String> {
+    ids.split(',')
+        .map(|id| {
+            id.trim()
+                .parse()
+                .map_err(|_| format!("`{id}` is no task id"))
+        })
+        .collect()
+}
+fn validate_task_ids(ids: &str) -> Result<(), ValidationError> {
+    parse_task_ids(ids).map(|_| ()).map_err(|message| {
+        let mut error = ValidationError::new("task_id");
+        error.message = Some(Cow::Owned(message));
+        error
+    })
 }
 pub async fn get_tasks_all(
-    State(db_conn): State<DatabaseConnection>,
-    Query(query_params): Query<GetTasksParams>,
-) -> Result<Json<Vec<ResponseTask>>, StatusCode> {
-    let mut condition = Condition::all();
-    if let Some(priority) = query_params.priority {
-        dbg!(&priority);
-        condition = if priority.is_empty() {
-            condition.add(tasks::Column::Priority.is_null())
-        } else {
-            condition.add(tasks::Column::Priority.eq(priority))
-        };
-    }
-    if let Some(title) = query_params.title {
-        dbg!(&title);
-        condition = if title.is_empty() {
-            condition.add(tasks::Column::Title.is_null())
-        } else {
-            condition.add(tasks::Column::Title.eq(title))
-        };
+    _: Require<TasksRead>,
+    State(tasks): State<DynTaskRepository>,
+    Extension(user): Extension<UserModel>,
+    Extension(claims): Extension<Claims>,
+    ValidatedQuery(query_params): ValidatedQuery<GetTasksParams>,
+) -> Result<Json<Page<ResponseTask>>, AppError> {
+    list_tasks(&tasks, task_owner(&user, &claims), query_params, false).await
+}
+// the soft deleted tasks, with the same filters and paging as `get_tasks_all`
+pub async fn get_tasks_trash(
+    _: Require<TasksRead>,
+    State(tasks): State<DynTaskRepository>,
+    Extension(user): Extension<UserModel>,
+    Extension(claims): Extension<Claims>,
+    ValidatedQuery(query_params): ValidatedQuery<GetTasksParams>,
+) -> Result<Json<Page<ResponseTask>>, AppError> {
+    list_tasks(&tasks, task_owner(&user, &claims), query_params, true).await
+}
+async fn list_tasks(
+    tasks: &DynTaskRepository,
+    owner: Option<i32>,
+    query_params: GetTasksParams,
+    deleted: bool,
+) -> Result<Json<Page<ResponseTask>>, AppError> {
+    if query_params.cursor.is_some() && query_params.offset.is_some() {
+        return Err(AppError::validation(
+            "page with either cursor or offset, not both",
+        ));
     }
+    let sort = parse_sort(query_params.sort.as_deref()).map_err(AppError::validation)?;
+    let after = match &query_params.cursor {
+        Some(cursor) => Some(decode_cursor(&sort, cursor)?),
+        None => None,
+    };
+    let limit = query_params.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
 
-    let tasks = Tasks::find()
-        .filter(condition)
-        .filter(tasks::Column::DeletedAt.is_null())
-        .all(&db_conn)
-        .await
-        .map_err(|_error| StatusCode::INTERNAL_SERVER_ERROR)?
-        .into_iter()
-        .map(|task| ResponseTask {
-            task_id: task.id,
-            title: task.title,
-            priority: task.priority,
-            description: task.description,
-            deleted_at: task.deleted_at,
-            user_id: task.user_id,
-        })
-        .collect();
-    Ok(Json(tasks))
+    let user_id = match (owner, query_params.owner) {
+        (None, owner) => owner,
+        (Some(own), Some(owner)) if owner != own => {
+            return Err(AppError::Forbidden(
+                "only admins can list the tasks of other users".to_owned(),
+            ));
+        }
+        (Some(own), _) => Some(own),
+    };
+    // an empty value looks for NULL
+    let filter = TaskFilter {
+        user_id,
+        deleted,
+        ids: match query_params.task_id.as_deref() {
+            Some(ids) => Some(parse_task_ids(ids).map_err(AppError::validation)?),
+            None => None,
+        },
+        priorities: query_params.priority.map(|priorities| {
+            priorities
+                .split(',')
+                .map(|priority| Some(priority.trim().to_owned()).filter(|p| !p.is_empty()))
+                .collect()
+        }),
+        title: query_params
+            .title
+            .map(|title| Some(title).filter(|t| !t.is_empty())),
+        completed: query_params.completed,
+        is_default: query_params.is_default,
+        created_from: query_params.created_from,
+        created_to: query_params.created_to,
+        completed_from: query_params.completed_from,
+        completed_to: query_params.completed_to,
+        search: query_params.q.map(|text| match query_params.search {
+            SearchMode::Substring => TaskSearch::Substring(text),
+            SearchMode::FullText => TaskSearch::FullText(text),
+        }),
+    };
+
+    let total = if query_params.total {
+        Some(tasks.count(filter.clone()).await?)
+    } else {
+        None
+    };
+    // one row more than asked for tells whether there is a next page
+    let page = TaskPage {
+        sort: sort.clone(),
+        after,
+        offset: query_params.offset.unwrap_or(0),
+        limit: limit + 1,
+    };
+    let mut items = tasks.list(filter, page).await?;
+    let next_cursor = if items.len() as u64 > limit {
+        items.truncate(limit as usize);
+        items.last().map(|task| encode_cursor(&sort, task))
+    } else {
+        None
+    };
+    Ok(Json(Page {
+        items: items.into_iter().map(ResponseTask::from).collect(),
+        next_cursor,
+        total,
+    }))
     //dbg!(&tasks);
 }
 //------------------== Rest Create(Add)
 // Deserialize for input json, Debug for terminal print
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Validate)]
 pub struct AddTask {
+    #[validate(length(min = 1, max = 255, message = "must have 1 to 255 characters"))]
     pub title: String,
+    #[validate(length(max = 4, message = "must have at most 4 characters"))]
     pub priority: Option<String>,
     pub description: Option<String>,
 }
@@ -425,69 +708,74 @@ pub struct ResponseAddTask {
     pub description: Option<String>,
 }
 pub async fn add_task(
-    State(db_conn): State<DatabaseConnection>,
+    _: Require<TasksWrite>,
+    State(tasks): State<DynTaskRepository>,
     Extension(user): Extension<UserModel>,
-    Json(json): Json<AddTask>,
+    ValidatedJson(json): ValidatedJson<AddTask>,
     //TypedHeader(auth): TypedHeader<Authorization<Bearer>>,
     //auth: TypedHeader<Authorization<Bearer>>,
-) -> Result<Json<ResponseAddTask>, StatusCode> {
-    let user = user.into_active_model();
-
-    let new_task = tasks::ActiveModel {
-        title: Set(json.title),
-        priority: Set(json.priority),
-        description: Set(json.description),
-        user_id: Set(Some(user.id.unwrap())),
-        ..Default::default()
+) -> Result<Json<ResponseAddTask>, AppError> {
+    let new_task = NewTask {
+        title: json.title,
+        priority: json.priority,
+        description: json.description,
+        user_id: Some(user.id),
     };
-    let saved_task = new_task
-        .save(&db_conn)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let saved_task = tasks.create(new_task).await?;
     dbg!(&saved_task);
     Ok(Json(ResponseAddTask {
-        title: saved_task.title.unwrap(),
-        priority: saved_task.priority.unwrap(),
-        description: saved_task.description.unwrap(),
+        title: saved_task.title,
+        priority: saved_task.priority,
+        description: saved_task.description,
     }))
 }
 //------------------== Rest Put(Replace or Atomic update)
 //PUT replacs the entire entity(overwrite any missing fields to null), while PATCH only updates the fields that you give it.
-#[derive(Deserialize, Debug)]
+#[allow(dead_code)]
+#[derive(Deserialize, Debug, Validate)]
 pub struct ReplaceTask {
     pub id: Option<i32>,
+    #[validate(length(max = 4, message = "must have at most 4 characters"))]
     pub priority: Option<String>,
+    #[validate(length(min = 1, max = 255, message = "must have 1 to 255 characters"))]
     pub title: String,
     pub completed_at: Option<DateTimeWithTimeZone>,
     pub description: Option<String>,
-    pub deleted_at: Option<DateTimeWithTimeZone>,
-    pub user_id: Option<i32>,
     pub is_default: Option<bool>,
-} //copied from entities/tasks.rs, change id to option so we keep the original id the same. Leave the rest unchange according to the DB settings
+} //copied from entities/tasks.rs, change id to option so we keep the original id the same. Leave the rest unchange according to the DB settings. user_id and deleted_at are left out, the owner stays the same and only restore takes a task out of the trash
 pub async fn replace_task(
-    State(db_conn): State<DatabaseConnection>,
-    Path(task_id): Path<i32>,
-    Json(json): Json<ReplaceTask>,
-) -> Result<String, StatusCode> {
-    let replacing_task = tasks::ActiveModel {
-        id: Set(task_id),
-        priority: Set(json.priority),
-        title: Set(json.title),
-        completed_at: Set(json.completed_at),
-        description: Set(json.description),
-        deleted_at: Set(json.deleted_at),
-        user_id: Set(json.user_id),
-        is_default: Set(json.is_default),
+    _: Require<TasksWrite>,
+    State(tasks): State<DynTaskRepository>,
+    Extension(user): Extension<UserModel>,
+    Extension(claims): Extension<Claims>,
+    ValidatedPath(task_id): ValidatedPath<i32>,
+    if_match: IfMatchHeader,
+    ValidatedJson(json): ValidatedJson<ReplaceTask>,
+) -> Result<TaskResponse, AppError> {
+    let existing_task = find_live_task(&tasks, task_owner(&user, &claims), task_id).await?;
+    let what = format!("task {task_id}");
+    if_match.check(&what, existing_task.version)?;
+    let replacing_task = TaskModel {
+        id: task_id,
+        priority: json.priority,
+        title: json.title,
+        completed_at: json.completed_at,
+        description: json.description,
+        deleted_at: existing_task.deleted_at,
+        user_id: existing_task.user_id,
+        is_default: json.is_default,
+        created_at: existing_task.created_at,
+        version: existing_task.version,
     };
-    Tasks::update(replacing_task)
-        .filter(tasks::Column::Id.eq(task_id))
-        .exec(&db_conn)
+    let task = tasks
+        .update(replacing_task)
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    Ok("ok".to_owned())
+        .map_err(|err| stale(err, &what))?;
+    Ok(task_response(task))
 }
 //------------------== Rest Patch
-#[derive(Deserialize, Debug)]
+#[allow(dead_code)]
+#[derive(Deserialize, Debug, Validate)]
 pub struct UpdatePartialTask {
     //Should not use serde_with with single option!!!
     pub id: Option<i32>,
@@ -496,8 +784,10 @@ pub struct UpdatePartialTask {
         skip_serializing_if = "Option::is_none",//serialization
         with = "::serde_with::rust::double_option",
     )]
+    #[validate(length(max = 4, message = "must have at most 4 characters"))]
     pub priority: Option<Option<String>>,
     //Should not be null, so do not add serde_with with double option macro here!!!
+    #[validate(length(min = 1, max = 255, message = "must have 1 to 255 characters"))]
     pub title: Option<String>,
     #[serde(
         default,// for deserialization
@@ -507,99 +797,140 @@ pub struct UpdatePartialTask {
     pub description: Option<Option<String>>,
 } // remove user_id, completed_at, deleted_at and is_default so those cannot be set!
 pub async fn update_partial_task(
-    State(db_conn): State<DatabaseConnection>,
-    Path(task_id): Path<i32>,
-    Json(json): Json<UpdatePartialTask>,
-) -> Result<String, StatusCode> {
-    let mut existing_task = if let Some(task) = Tasks::find_by_id(task_id)
-        .one(&db_conn)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-    {
-        task.into_active_model() //need active model
-    } else {
-        return Err(StatusCode::NOT_FOUND);
-    };
+    _: Require<TasksWrite>,
+    State(tasks): State<DynTaskRepository>,
+    Extension(user): Extension<UserModel>,
+    Extension(claims): Extension<Claims>,
+    ValidatedPath(task_id): ValidatedPath<i32>,
+    if_match: IfMatchHeader,
+    ValidatedJson(json): ValidatedJson<UpdatePartialTask>,
+) -> Result<TaskResponse, AppError> {
+    let mut existing_task = find_live_task(&tasks, task_owner(&user, &claims), task_id).await?;
+    let what = format!("task {task_id}");
+    if_match.check(&what, existing_task.version)?;
 
     //if the priority field is set, even it is set to null
     if let Some(priority) = json.priority {
-        existing_task.priority = Set(priority);
+        existing_task.priority = priority;
     }
     if let Some(description) = json.description {
-        existing_task.description = Set(description);
+        existing_task.description = description;
     }
     if let Some(title) = json.title {
-        existing_task.title = Set(title); //single option
+        existing_task.title = title; //single option
     }
 
     dbg!(&existing_task);
-    Tasks::update(existing_task)
-        .filter(tasks::Column::Id.eq(task_id))
-        .exec(&db_conn)
+    let task = tasks
+        .update(existing_task)
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    Ok("ok".to_owned())
+        .map_err(|err| stale(err, &what))?;
+    Ok(task_response(task))
+}
+
+//------------------== Completion
+// completing a completed task keeps the time it was first completed at
+pub async fn complete_task(
+    _: Require<TasksWrite>,
+    State(tasks): State<DynTaskRepository>,
+    Extension(user): Extension<UserModel>,
+    Extension(claims): Extension<Claims>,
+    ValidatedPath(task_id): ValidatedPath<i32>,
+) -> Result<TaskResponse, AppError> {
+    set_completed(&tasks, task_owner(&user, &claims), task_id, true).await
+}
+pub async fn reopen_task(
+    _: Require<TasksWrite>,
+    State(tasks): State<DynTaskRepository>,
+    Extension(user): Extension<UserModel>,
+    Extension(claims): Extension<Claims>,
+    ValidatedPath(task_id): ValidatedPath<i32>,
+) -> Result<TaskResponse, AppError> {
+    set_completed(&tasks, task_owner(&user, &claims), task_id, false).await
+}
+async fn set_completed(
+    tasks: &DynTaskRepository,
+    owner: Option<i32>,
+    task_id: i32,
+    completed: bool,
+) -> Result<TaskResponse, AppError> {
+    let mut task = find_live_task(tasks, owner, task_id).await?;
+    if completed == task.completed_at.is_some() {
+        return Ok(task_response(task));
+    }
+    task.completed_at = completed.then(|| Utc::now().into());
+    let task = tasks
+        .update(task)
+        .await
+        .map_err(|err| stale(err, &format!("task {task_id}")))?;
+    Ok(task_response(task))
+}
+
+//------------------== Trash
+// restoring a task that is not in the trash changes nothing
+pub async fn restore_task(
+    _: Require<TasksWrite>,
+    State(tasks): State<DynTaskRepository>,
+    Extension(user): Extension<UserModel>,
+    Extension(claims): Extension<Claims>,
+    ValidatedPath(task_id): ValidatedPath<i32>,
+) -> Result<TaskResponse, AppError> {
+    let mut task = find_task(&tasks, task_owner(&user, &claims), task_id).await?;
+    if task.deleted_at.is_none() {
+        return Ok(task_response(task));
+    }
+    task.deleted_at = None;
+    let task = tasks
+        .update(task)
+        .await
+        .map_err(|err| stale(err, &format!("task {task_id}")))?;
+    Ok(task_response(task))
 }
 
 //------------------== Rest Delete
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Validate)]
 pub struct QueryParamsDelete {
-    is_soft: bool,
+    /// Soft delete into the trash unless `is_soft=false`
+    is_soft: Option<bool>,
 }
 pub async fn delete_task(
-    State(db_conn): State<DatabaseConnection>,
-    Path(task_id): Path<i32>,
-    Query(query_params): Query<QueryParamsDelete>,
-) -> Result<String, StatusCode> {
-    let mut existing_task = if let Some(task) = Tasks::find_by_id(task_id)
-        .one(&db_conn)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-    {
-        task.into_active_model() //need active model
-    } else {
-        return Err(StatusCode::NOT_FOUND);
-    };
+    _: Require<TasksWrite>,
+    State(tasks): State<DynTaskRepository>,
+    Extension(user): Extension<UserModel>,
+    Extension(claims): Extension<Claims>,
+    ValidatedPath(task_id): ValidatedPath<i32>,
+    ValidatedQuery(query_params): ValidatedQuery<QueryParamsDelete>,
+    if_match: IfMatchHeader,
+) -> Result<String, AppError> {
+    let mut existing_task = find_task(&tasks, task_owner(&user, &claims), task_id).await?;
     dbg!(&existing_task);
-    if query_params.is_soft {
-        dbg!("do soft delete"); //Note: soft delete can be recovered if you do a partial update and set deleted_at to null!
+    let what = format!("task {task_id}");
+    if_match.check(&what, existing_task.version)?;
+    if query_params.is_soft.unwrap_or(true) {
+        dbg!("do soft delete"); //Note: soft deleted tasks are restored with POST /tasks/:id/restore
+
+        // a task in the trash keeps the time it was first deleted at, its retention runs from there
+        if existing_task.deleted_at.is_some() {
+            return Err(AppError::NotFound(format!("task {task_id} not found")));
+        }
         let now = chrono::Utc::now();
-        existing_task.deleted_at = Set(Some(now.into()));
+        existing_task.deleted_at = Some(now.into());
 
-        Tasks::update(existing_task)
-            .filter(tasks::Column::Id.eq(task_id))
-            .exec(&db_conn)
+        tasks
+            .update(existing_task)
             .await
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            .map_err(|err| stale(err, &what))?;
         Ok("ok".to_owned())
     } else {
         dbg!("do hard delete");
-        let delete_result = Tasks::delete(existing_task)
-            .exec(&db_conn)
+        tasks
+            .delete_versioned(existing_task.id, existing_task.version)
             .await
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-        if delete_result.rows_affected != 1 {
-            return Err(StatusCode::EXPECTATION_FAILED);
-        }
+            .map_err(|err| stale(err, &what))?;
         Ok("ok".to_owned())
-        /*
-        if you do not want to check if the item exists:
-        # Use delete_by_id
-        Tasks::delete_by_id(task_id)
-        .exec(&db_conn)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-        # Use delete_many + filter ...
-        Tasks::delete_many()
-        .filter(tasks::Column::Id.eq(task_id))
-        .exec(&db_conn)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-        */
     }
 }
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Default)]
 pub struct RespBlockchain {
     pub num1: Option<String>,
     pub num2: Option<String>,
@@ -607,18 +938,14 @@ pub struct RespBlockchain {
     pub txn_hash: Option<String>,
     pub error: Option<String>,
 }
-impl Default for RespBlockchain {
-    fn default() -> Self {
-        Self {
-            num1: None,
-            num2: None,
-            address: None,
-            txn_hash: None,
-            error: None,
-        }
-    }
+fn chain_config(config: &Config) -> Result<&ChainConfig, AppError> {
+    config
+        .chain
+        .as_ref()
+        .ok_or_else(|| AppError::Chain("blockchain is not configured".to_owned()))
 }
-#[derive(Deserialize, Debug)]
+#[allow(dead_code)]
+#[derive(Deserialize, Debug, Validate)]
 pub struct ReqBlockchain {
     pub num1: Option<f64>,
     pub num2: Option<f64>,
@@ -626,39 +953,48 @@ pub struct ReqBlockchain {
     pub addr2: Option<String>,
 }
 pub async fn eth_local_txn(
-    State(_db_conn): State<DatabaseConnection>,
-    Json(json): Json<ReqBlockchain>,
-) -> Result<Json<RespBlockchain>, String> {
+    _: Require<EthWrite>,
+    State(config): State<Arc<Config>>,
+    ValidatedJson(json): ValidatedJson<ReqBlockchain>,
+) -> Result<Json<RespBlockchain>, AppError> {
     println!("eth_local_txn");
     dbg!(&json);
+    let chain = chain_config(&config)?;
 
-    let _txn_result = ethereum_local_txn().await.map_err(|_e| "err".to_owned())?;
+    ethereum_local_txn(&chain.mnemonic, chain.addr1)
+        .await
+        .map_err(|e| AppError::Chain(e.to_string()))?;
     Ok(Json(RespBlockchain {
         ..Default::default()
     }))
 }
 pub async fn eth_deploy_contract(
-    State(_db_conn): State<DatabaseConnection>,
-    Json(json): Json<ReqBlockchain>,
-) -> Result<Json<RespBlockchain>, String> {
+    _: Require<EthWrite>,
+    State(config): State<Arc<Config>>,
+    ValidatedJson(json): ValidatedJson<ReqBlockchain>,
+) -> Result<Json<RespBlockchain>, AppError> {
     println!("eth_deploy_contract");
     dbg!(&json);
+    let chain = chain_config(&config)?;
 
-    let _txn_result = compile_deploy_contract()
+    compile_deploy_contract(&chain.mnemonic)
         .await
-        .map_err(|_e| "err".to_owned())?;
+        .map_err(|e| AppError::Chain(e.to_string()))?;
     Ok(Json(RespBlockchain {
         ..Default::default()
     }))
 }
 pub async fn eth_live_read(
-    State(_db_conn): State<DatabaseConnection>,
-    Json(json): Json<ReqBlockchain>,
-) -> Result<Json<RespBlockchain>, String> {
+    State(config): State<Arc<Config>>,
+    ValidatedJson(json): ValidatedJson<ReqBlockchain>,
+) -> Result<Json<RespBlockchain>, AppError> {
     println!("eth_live_read");
     dbg!(&json);
+    let chain = chain_config(&config)?;
 
-    let (bal0, bal1) = ethereum_live_read().await.map_err(|e| e.to_string())?;
+    let (bal0, bal1) = ethereum_live_read(chain)
+        .await
+        .map_err(|e| AppError::Chain(e.to_string()))?;
     Ok(Json(RespBlockchain {
         num1: Some(bal0),
         num2: Some(bal1),
@@ -666,16 +1002,21 @@ pub async fn eth_live_read(
     }))
 }
 pub async fn eth_live_write(
-    State(_db_conn): State<DatabaseConnection>,
-    Json(json): Json<ReqBlockchain>,
-) -> Result<Json<RespBlockchain>, String> {
+    _: Require<EthWrite>,
+    State(config): State<Arc<Config>>,
+    State(shutdown): State<Shutdown>,
+    ValidatedJson(json): ValidatedJson<ReqBlockchain>,
+) -> Result<Json<RespBlockchain>, AppError> {
     println!("eth_live_write");
     dbg!(&json);
-    let amount = json.num1.ok_or_else(|| "num1 missing".to_owned())?;
+    let chain = chain_config(&config)?;
+    let amount = json
+        .num1
+        .ok_or_else(|| AppError::validation("num1 missing"))?;
 
-    let (txn_hash, balance1) = ethereum_live_write(amount)
+    let (txn_hash, balance1) = ethereum_live_write(chain, amount, &shutdown.request_token())
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(|e| AppError::Chain(e.to_string()))?;
     Ok(Json(RespBlockchain {
         num1: Some(balance1),
         txn_hash: Some(txn_hash),
@@ -683,17 +1024,24 @@ pub async fn eth_live_write(
     }))
 }
 pub async fn eth_send_ether(
-    State(_db_conn): State<DatabaseConnection>,
-    Json(json): Json<ReqBlockchain>,
-) -> Result<Json<RespBlockchain>, String> {
+    _: Require<EthWrite>,
+    State(config): State<Arc<Config>>,
+    State(shutdown): State<Shutdown>,
+    ValidatedJson(json): ValidatedJson<ReqBlockchain>,
+) -> Result<Json<RespBlockchain>, AppError> {
     println!("eth_send_ether");
     dbg!(&json);
-    let amount = json.num1.ok_or_else(|| "num1 missing".to_owned())?;
-    let addr1 = json.addr1.ok_or_else(|| "addr1 missing".to_owned())?;
+    let chain = chain_config(&config)?;
+    let amount = json
+        .num1
+        .ok_or_else(|| AppError::validation("num1 missing"))?;
+    let addr1 = json
+        .addr1
+        .ok_or_else(|| AppError::validation("addr1 missing"))?;
 
-    let (txn_hash, balance1) = ethereum_send_ether(addr1, amount)
+    let (txn_hash, balance1) = ethereum_send_ether(chain, addr1, amount, &shutdown.request_token())
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(|e| AppError::Chain(e.to_string()))?;
     Ok(Json(RespBlockchain {
         num1: Some(balance1),
         txn_hash: Some(txn_hash),
@@ -701,11 +1049,14 @@ pub async fn eth_send_ether(
     }))
 }
 pub async fn chainlink_prices(
-    State(_db_conn): State<DatabaseConnection>,
-    //Json(json): Json<ReqBlockchain>,
-) -> Result<Json<RespBlockchain>, String> {
+    State(config): State<Arc<Config>>,
+    //ValidatedJson(json): ValidatedJson<ReqBlockchain>,
+) -> Result<Json<RespBlockchain>, AppError> {
     println!("get_ether_price");
-    let (btc_price, eth_price) = get_chainlink_prices().await.map_err(|e| e.to_string())?;
+    let chain = chain_config(&config)?;
+    let (btc_price, eth_price) = get_chainlink_prices(chain)
+        .await
+        .map_err(|e| AppError::Chain(e.to_string()))?;
     Ok(Json(RespBlockchain {
         num1: Some(btc_price),
         num2: Some(eth_price),
@@ -714,18 +1065,18 @@ pub async fn chainlink_prices(
 }
 pub async fn make_keypair2(
     State(_db_conn): State<DatabaseConnection>,
-    //Json(json): Json<ReqBlockchain>,
-) -> Result<Json<RespBlockchain>, String> {
+    //ValidatedJson(json): ValidatedJson<ReqBlockchain>,
+) -> Result<Json<RespBlockchain>, AppError> {
     println!("make_keypair2");
-    let _out = make_keypair1().map_err(|e| e.to_string())?;
+    make_keypair1().map_err(|e| AppError::Internal(e.to_string()))?;
     Ok(Json(RespBlockchain {
         ..Default::default()
     }))
 }
 pub async fn run_thread(
     State(_db_conn): State<DatabaseConnection>,
-    Json(json): Json<ReqBlockchain>,
-) -> Result<Json<RespBlockchain>, String> {
+    ValidatedJson(json): ValidatedJson<ReqBlockchain>,
+) -> Result<Json<RespBlockchain>, AppError> {
     println!("run_thread");
     dbg!(&json);
     dbg!(json);
@@ -738,21 +1089,24 @@ pub async fn run_thread(
         }
         tx.send(1000).map_err(|err| {
             println!("err1: {err}");
-            "tx.send() failed".to_owned()
+            AppError::Internal("tx.send() failed".to_owned())
         })
     });
     handle
         .join()
-        .map_err(|_e| "handle.join() failed".to_owned())??;
+        .map_err(|_e| AppError::Internal("handle.join() failed".to_owned()))??;
 
     println!("main thread continue after waiting for the thread");
-    let _out = rx.recv().map_err(|_e| "rx.recv() failed".to_owned())?;
+    let _out = rx
+        .recv()
+        .map_err(|_e| AppError::Internal("rx.recv() failed".to_owned()))?;
     Ok(Json(RespBlockchain {
         ..Default::default()
     }))
 }
 
 use reqwest::header::USER_AGENT;
+#[allow(dead_code)]
 #[derive(Deserialize, Debug)]
 pub struct Item {
     pub login: String,
@@ -762,7 +1116,7 @@ pub struct Item {
 pub async fn make_get_request(
     State(_db_conn): State<DatabaseConnection>,
     //Path(task_id): Path<i32>,
-) -> Result<Json<RespBlockchain>, String> {
+) -> Result<Json<RespBlockchain>, AppError> {
     let request_url = format!(
         "https://api.github.com/repos/{owner}/{repo}/stargazers",
         owner = "rust-lang-nursery",
@@ -776,9 +1130,12 @@ pub async fn make_get_request(
         .header(USER_AGENT, "demo")
         .send()
         .await
-        .map_err(|_| "err@send()".to_owned())?;
+        .map_err(|e| AppError::Upstream(format!("send(): {e}")))?;
 
-    let users: Vec<Item> = response.json().await.map_err(|_| "err@json()".to_owned())?;
+    let users: Vec<Item> = response
+        .json()
+        .await
+        .map_err(|e| AppError::Upstream(format!("json(): {e}")))?;
     println!("users: {:?}", users);
     /*let resp = reqwest::get("https://httpbin.org/ip")
             .await?
@@ -794,8 +1151,8 @@ pub async fn make_get_request(
 //curl localhost:3000/make_get_request
 pub async fn make_post_request(
     State(_db_conn): State<DatabaseConnection>,
-    //Json(json): Json<ReqBlockchain>,
-) -> Result<Json<RespBlockchain>, String> {
+    //ValidatedJson(json): ValidatedJson<ReqBlockchain>,
+) -> Result<Json<RespBlockchain>, AppError> {
     // This will POST a body of `{"lang":"rust","body":"json"}`
     let mut map = HashMap::new();
     map.insert("lang", "rust");
@@ -807,7 +1164,7 @@ pub async fn make_post_request(
         .json(&map)
         .send()
         .await
-        .map_err(|_| "err@send()".to_owned())?;
+        .map_err(|e| AppError::Upstream(format!("send(): {e}")))?;
     println!("res: {:?}", res);
     Ok(Json(RespBlockchain {
         ..Default::default()
@@ -827,24 +1184,24 @@ error_chain! {
 
 pub async fn download_file(
     State(_db_conn): State<DatabaseConnection>,
-    //Json(json): Json<ReqBlockchain>,
-) -> Result<Json<RespBlockchain>, String> {
+    //ValidatedJson(json): ValidatedJson<ReqBlockchain>,
+) -> Result<Json<RespBlockchain>, AppError> {
     let tmp_dir = Builder::new()
         .prefix("example")
         .tempdir()
-        .map_err(|_| "err@tempdir()".to_owned())?;
+        .map_err(|e| AppError::Internal(format!("tempdir(): {e}")))?;
     let target = "https://www.rust-lang.org/logos/rust-logo-512x512.png";
     println!("download_file 1");
     let response = reqwest::get(target)
         .await
-        .map_err(|_| "err@reqwest.get()".to_owned())?;
+        .map_err(|e| AppError::Upstream(format!("reqwest.get(): {e}")))?;
     println!("download_file 2. response:{:?}", &response);
 
     let mut dest = {
         let fname = response
             .url()
             .path_segments()
-            .and_then(|segments| segments.last())
+            .and_then(|mut segments| segments.next_back())
             .and_then(|name| if name.is_empty() { None } else { Some(name) })
             .unwrap_or("tmp.bin");
         println!("file to download: '{}'", fname);
@@ -853,17 +1210,18 @@ pub async fn download_file(
         println!("will be located at: '{:?}'", fname);
         File::create(fname)
     }
-    .map_err(|_| "err@File::create()".to_owned())?;
+    .map_err(|e| AppError::Internal(format!("File::create(): {e}")))?;
     println!("download_file 3. dest:{:?}", &dest);
 
     let content = response
         .text()
         .await
-        .map_err(|_| "err@response.text()".to_owned())?;
+        .map_err(|e| AppError::Upstream(format!("response.text(): {e}")))?;
     println!("download_file 4");
     //println!("download_file 4. content:{}", &content);
 
-    let out = copy(&mut content.as_bytes(), &mut dest).map_err(|_| "err@copy())".to_owned())?;
+    let out = copy(&mut content.as_bytes(), &mut dest)
+        .map_err(|e| AppError::Internal(format!("copy(): {e}")))?;
     println!("download_file 5. out:{}", out);
 
     Ok(Json(RespBlockchain {
@@ -0,0 +1,232 @@
+//! Every login opens a session: one row per device with its user agent, ip and when it was last
+//! seen. The session id is the `sid` claim of the access tokens and the family of the refresh
+//! tokens, so revoking a session logs that device out for good.
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts, State},
+    headers::UserAgent,
+    http::{request::Parts, Request},
+    middleware::Next,
+    response::Response,
+    Extension, Json, TypedHeader,
+};
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::Serialize;
+use std::{convert::Infallible, net::SocketAddr};
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    entities::{sessions::Model as SessionModel, users::Model as UserModel},
+    error::AppError,
+    repository::{DynRefreshTokenRepository, DynSessionRepository, NewRefreshToken, NewSession},
+    utils::{hash_token, make_jwt, make_random_token},
+    validation::ValidatedPath,
+};
+
+/// Who is calling, recorded on new sessions. Both parts are missing when unknown
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let user_agent = Option::<TypedHeader<UserAgent>>::from_request_parts(parts, state).await?;
+        // the peer address, there is no proxy header support
+        let connect_info =
+            Option::<ConnectInfo<SocketAddr>>::from_request_parts(parts, state).await?;
+        Ok(Self {
+            user_agent: user_agent.map(|TypedHeader(user_agent)| user_agent.to_string()),
+            ip: connect_info.map(|ConnectInfo(addr)| addr.ip().to_string()),
+        })
+    }
+}
+
+/// Tokens handed out when a session starts or is refreshed
+pub struct SessionTokens {
+    pub token: String,
+    pub refresh_token: String,
+}
+
+// last_seen_at is only written when it is older than this, not on every request
+pub const LAST_SEEN_RESOLUTION_SECS: i64 = 60;
+
+pub fn is_active(session: &SessionModel) -> bool {
+    session.revoked_at.is_none() && session.expires_at > Utc::now()
+}
+
+fn refresh_expiry(config: &Config) -> DateTime<FixedOffset> {
+    let ttl = chrono::Duration::seconds(config.auth.refresh_token_ttl.as_secs() as i64);
+    (Utc::now() + ttl).into()
+}
+
+pub async fn start_session(
+    sessions: &DynSessionRepository,
+    refresh_tokens: &DynRefreshTokenRepository,
+    config: &Config,
+    user: &UserModel,
+    client: ClientInfo,
+) -> Result<SessionTokens, AppError> {
+    let session = sessions
+        .create(NewSession {
+            id: Uuid::new_v4().to_string(),
+            user_id: user.id,
+            user_agent: client.user_agent,
+            ip: client.ip,
+            expires_at: refresh_expiry(config),
+        })
+        .await?;
+    issue_tokens(refresh_tokens, config, user, &session).await
+}
+
+/// Push the expiry of the session out and hand out a new access and refresh token for it.
+/// Fails when the session was revoked since it was read, e.g. by reuse detection
+pub async fn extend_session(
+    sessions: &DynSessionRepository,
+    refresh_tokens: &DynRefreshTokenRepository,
+    config: &Config,
+    user: &UserModel,
+    mut session: SessionModel,
+) -> Result<SessionTokens, AppError> {
+    session.last_seen_at = Utc::now().into();
+    session.expires_at = refresh_expiry(config);
+    let extended = sessions
+        .extend(&session.id, session.last_seen_at, session.expires_at)
+        .await?;
+    if !extended {
+        return Err(AppError::Unauthorized("session revoked".to_owned()));
+    }
+    issue_tokens(refresh_tokens, config, user, &session).await
+}
+
+// the refresh token is stored as a hash only, the plain one goes to the client once.
+// The role is read from the user each time, so a changed role applies from the next refresh
+async fn issue_tokens(
+    refresh_tokens: &DynRefreshTokenRepository,
+    config: &Config,
+    user: &UserModel,
+    session: &SessionModel,
+) -> Result<SessionTokens, AppError> {
+    let refresh_token = make_random_token();
+    refresh_tokens
+        .create(NewRefreshToken {
+            user_id: session.user_id,
+            family_id: session.id.clone(),
+            token_hash: hash_token(&refresh_token),
+            expires_at: session.expires_at,
+        })
+        .await?;
+    Ok(SessionTokens {
+        token: make_jwt(&config.auth, user.id, &session.id, vec![user.role.clone()])?,
+        refresh_token,
+    })
+}
+
+/// Keep `last_seen_at` roughly current without a write per request. Fails when the session was
+/// revoked since it was read
+pub async fn touch_session(
+    sessions: &DynSessionRepository,
+    mut session: SessionModel,
+) -> Result<SessionModel, AppError> {
+    let now = Utc::now();
+    if (now - session.last_seen_at.with_timezone(&Utc)).num_seconds() < LAST_SEEN_RESOLUTION_SECS {
+        return Ok(session);
+    }
+    session.last_seen_at = now.into();
+    if !sessions.touch(&session.id, session.last_seen_at).await? {
+        return Err(AppError::Unauthorized("session revoked".to_owned()));
+    }
+    Ok(session)
+}
+
+/// Route layer below `auth` for the account routes: they need a login, an API key can't change
+/// passwords, sessions or other API keys
+pub async fn require_session<B>(request: Request<B>, next: Next<B>) -> Result<Response, AppError> {
+    if request.extensions().get::<SessionModel>().is_none() {
+        return Err(AppError::Forbidden(
+            "API keys can't be used here, log in instead".to_owned(),
+        ));
+    }
+    Ok(next.run(request).await)
+}
+
+pub async fn revoke_session(
+    sessions: &DynSessionRepository,
+    refresh_tokens: &DynRefreshTokenRepository,
+    session: SessionModel,
+) -> Result<(), AppError> {
+    refresh_tokens.revoke_family(&session.id).await?;
+    sessions.revoke(&session.id).await?;
+    Ok(())
+}
+
+#[derive(Serialize, Debug)]
+pub struct ResponseSession {
+    pub id: String,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: DateTime<FixedOffset>,
+    pub last_seen_at: DateTime<FixedOffset>,
+    pub expires_at: DateTime<FixedOffset>,
+    /// The session of the token making this request
+    pub current: bool,
+}
+
+pub async fn list_sessions(
+    State(sessions): State<DynSessionRepository>,
+    Extension(user): Extension<UserModel>,
+    Extension(current): Extension<SessionModel>,
+) -> Result<Json<Vec<ResponseSession>>, AppError> {
+    let active = sessions.list_active(user.id).await?;
+    Ok(Json(
+        active
+            .into_iter()
+            .map(|session| ResponseSession {
+                current: session.id == current.id,
+                id: session.id,
+                user_agent: session.user_agent,
+                ip: session.ip,
+                created_at: session.created_at,
+                last_seen_at: session.last_seen_at,
+                expires_at: session.expires_at,
+            })
+            .collect(),
+    ))
+}
+
+// sessions of other users answer 404 as well, their ids are not confirmed to exist
+pub async fn delete_session(
+    State(sessions): State<DynSessionRepository>,
+    State(refresh_tokens): State<DynRefreshTokenRepository>,
+    Extension(user): Extension<UserModel>,
+    ValidatedPath(session_id): ValidatedPath<String>,
+) -> Result<String, AppError> {
+    let session = sessions.find_by_id(&session_id).await?;
+    let Some(session) = session.filter(|session| session.user_id == user.id && is_active(session))
+    else {
+        return Err(AppError::NotFound(format!(
+            "session {session_id} not found"
+        )));
+    };
+    revoke_session(&sessions, &refresh_tokens, session).await?;
+    Ok("ok".to_owned())
+}
+
+#[derive(Serialize, Debug)]
+pub struct ResponseRevoked {
+    pub revoked: u64,
+}
+// the current session is revoked too, so the client has to log in again
+pub async fn delete_all_sessions(
+    State(sessions): State<DynSessionRepository>,
+    Extension(user): Extension<UserModel>,
+) -> Result<Json<ResponseRevoked>, AppError> {
+    // refresh needs an active session, so the refresh tokens of these sessions stop working too
+    let revoked = sessions.revoke_all(user.id).await?;
+    Ok(Json(ResponseRevoked { revoked }))
+}
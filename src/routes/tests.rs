@@ -0,0 +1,513 @@
+//! Handler tests on the full router, with the in-memory repositories in place of a database
+use axum::{
+    async_trait,
+    body::{Body, HttpBody},
+    http::{header, HeaderMap, Method, Request, StatusCode},
+    Router,
+};
+use sea_orm::DatabaseConnection;
+use serde_json::{json, Value};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tower::ServiceExt;
+
+use super::{router, AppState};
+use crate::{
+    config::{
+        AuthConfig, Config, DatabaseConfig, HealthConfig, LoginConfig, MailConfig, MailTransport,
+        PoolConfig, ServerConfig, TrashConfig,
+    },
+    mailer::{Mail, Mailer},
+    repository::memory::{
+        MemoryApiKeyRepository, MemoryRefreshTokenRepository, MemorySessionRepository,
+        MemoryTaskRepository, MemoryUserRepository, MemoryUserTokenRepository,
+    },
+    shutdown::Shutdown,
+    throttle::LoginThrottle,
+};
+
+const PASSWORD: &str = "secret123";
+
+// keeps every mail instead of sending it
+#[derive(Default)]
+struct SentMails(Mutex<Vec<Mail>>);
+
+#[async_trait]
+impl Mailer for SentMails {
+    async fn send(&self, mail: Mail) -> eyre::Result<()> {
+        self.0.lock().unwrap().push(mail);
+        Ok(())
+    }
+}
+
+fn config() -> Config {
+    let pool = PoolConfig {
+        max_connections: None,
+        min_connections: None,
+        connect_timeout: None,
+        acquire_timeout: None,
+        idle_timeout: None,
+        max_lifetime: None,
+        sqlx_logging: false,
+    };
+    Config {
+        server: ServerConfig {
+            host: [127, 0, 0, 1].into(),
+            port: 0,
+            mode: "test".to_owned(),
+            shutdown_timeout: Duration::from_secs(1),
+            tls: None,
+        },
+        database: DatabaseConfig {
+            url: "sqlite::memory:".to_owned(),
+            pool,
+        },
+        auth: AuthConfig {
+            jwt_secret: "test secret".to_owned(),
+            jwt_issuer: "axum-server".to_owned(),
+            jwt_audience: "axum-server".to_owned(),
+            access_token_ttl: Duration::from_secs(60),
+            refresh_token_ttl: Duration::from_secs(60 * 60),
+            email_verify_ttl: Duration::from_secs(60 * 60),
+            password_reset_ttl: Duration::from_secs(60 * 60),
+            // the lowest cost bcrypt takes, the tests hash a lot
+            hash_cost: 4,
+        },
+        health: HealthConfig {
+            check_rpc: false,
+            timeout: Duration::from_secs(1),
+        },
+        login: LoginConfig {
+            max_attempts: 5,
+            ip_max_attempts: 20,
+            lockout: Duration::from_secs(30),
+            max_lockout: Duration::from_secs(60),
+        },
+        trash: TrashConfig {
+            retention: None,
+            purge_interval: Duration::from_secs(60 * 60),
+        },
+        mail: MailConfig {
+            transport: Some(MailTransport::Log),
+            from: "axum-server <noreply@localhost>".to_owned(),
+            public_url: None,
+        },
+        chain: None,
+    }
+}
+
+struct TestApp {
+    router: Router,
+    mails: Arc<SentMails>,
+}
+
+struct Reply {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Value,
+}
+
+impl Reply {
+    fn code(&self) -> &str {
+        self.body["code"].as_str().unwrap_or_default()
+    }
+}
+
+impl TestApp {
+    fn new() -> Self {
+        let config = config();
+        let mails = Arc::new(SentMails::default());
+        let app_state = AppState {
+            mode: config.server.mode.clone(),
+            db_conn: DatabaseConnection::Disconnected,
+            users: Arc::new(MemoryUserRepository::new()),
+            tasks: Arc::new(MemoryTaskRepository::new()),
+            sessions: Arc::new(MemorySessionRepository::new()),
+            refresh_tokens: Arc::new(MemoryRefreshTokenRepository::new()),
+            user_tokens: Arc::new(MemoryUserTokenRepository::new()),
+            api_keys: Arc::new(MemoryApiKeyRepository::new()),
+            mailer: mails.clone(),
+            login_throttle: Arc::new(LoginThrottle::new(config.login.clone())),
+            config: Arc::new(config),
+            shutdown: Shutdown::new(),
+        };
+        Self {
+            router: router(app_state),
+            mails,
+        }
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+        headers: &[(&str, &str)],
+        body: Option<Value>,
+    ) -> Reply {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let request = match body {
+            Some(body) => request
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        };
+        let response = self.router.clone().oneshot(request.unwrap()).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let mut body = response.into_body();
+        let mut bytes = Vec::new();
+        while let Some(chunk) = body.data().await {
+            bytes.extend_from_slice(&chunk.unwrap());
+        }
+        // plain text bodies like "ok" are kept as a json string
+        let body = serde_json::from_slice(&bytes)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()));
+        Reply {
+            status,
+            headers,
+            body,
+        }
+    }
+
+    /// Signs up and returns the access and refresh token
+    async fn sign_up(&self, username: &str) -> (String, String) {
+        let reply = self
+            .send(
+                Method::POST,
+                "/users",
+                None,
+                &[],
+                Some(json!({
+                    "username": username,
+                    "password": PASSWORD,
+                    "email": format!("{username}@example.com"),
+                })),
+            )
+            .await;
+        assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+        (
+            reply.body["token"].as_str().unwrap().to_owned(),
+            reply.body["refresh_token"].as_str().unwrap().to_owned(),
+        )
+    }
+
+    /// Adds a task and returns its id
+    async fn add_task(&self, token: &str, title: &str) -> i64 {
+        let reply = self
+            .send(
+                Method::POST,
+                "/add_task",
+                Some(token),
+                &[],
+                Some(json!({ "title": title })),
+            )
+            .await;
+        assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+        let reply = self
+            .send(
+                Method::GET,
+                "/tasks?sort=-id&limit=1",
+                Some(token),
+                &[],
+                None,
+            )
+            .await;
+        reply.body["items"][0]["task_id"].as_i64().unwrap()
+    }
+}
+
+#[tokio::test]
+async fn sign_up_sends_a_verification_mail_and_logs_in() {
+    let app = TestApp::new();
+    app.sign_up("alice").await;
+    let mails = app.mails.0.lock().unwrap().clone();
+    assert_eq!(mails.len(), 1);
+    assert_eq!(mails[0].to, "alice@example.com");
+
+    let login = |password: &'static str| {
+        app.send(
+            Method::POST,
+            "/users/login",
+            None,
+            &[],
+            Some(json!({ "username": "alice", "password": password })),
+        )
+    };
+    let reply = login("wrong password").await;
+    assert_eq!(reply.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(reply.code(), "unauthorized");
+    let reply = login(PASSWORD).await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert!(reply.body["token"].is_string());
+}
+
+#[tokio::test]
+async fn sign_up_rejects_a_taken_username() {
+    let app = TestApp::new();
+    app.sign_up("alice").await;
+    let reply = app
+        .send(
+            Method::POST,
+            "/users",
+            None,
+            &[],
+            Some(json!({
+                "username": "alice",
+                "password": PASSWORD,
+                "email": "other@example.com",
+            })),
+        )
+        .await;
+    assert_eq!(reply.status, StatusCode::CONFLICT);
+    assert_eq!(reply.code(), "conflict");
+}
+
+#[tokio::test]
+async fn tasks_need_a_token() {
+    let app = TestApp::new();
+    let reply = app.send(Method::GET, "/tasks", None, &[], None).await;
+    assert_eq!(reply.status, StatusCode::UNAUTHORIZED);
+    let reply = app
+        .send(Method::GET, "/tasks", Some("not a jwt"), &[], None)
+        .await;
+    assert_eq!(reply.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn if_match_rejects_a_stale_version() {
+    let app = TestApp::new();
+    let (token, _) = app.sign_up("alice").await;
+    let id = app.add_task(&token, "first").await;
+    let uri = format!("/tasks/{id}");
+
+    let reply = app.send(Method::GET, &uri, Some(&token), &[], None).await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert_eq!(reply.headers[header::ETAG], "\"1\"");
+
+    let patch = |if_match: &'static str, title: &'static str| {
+        let (app, uri, token) = (&app, &uri, &token);
+        async move {
+            let headers = [("if-match", if_match)];
+            app.send(
+                Method::PATCH,
+                uri,
+                Some(token),
+                &headers,
+                Some(json!({ "title": title })),
+            )
+            .await
+        }
+    };
+    let reply = patch("\"1\"", "second").await;
+    assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+    assert_eq!(reply.headers[header::ETAG], "\"2\"");
+    assert_eq!(reply.body["title"], "second");
+
+    let reply = patch("\"1\"", "third").await;
+    assert_eq!(reply.status, StatusCode::PRECONDITION_FAILED);
+    assert_eq!(reply.code(), "precondition_failed");
+    let reply = app.send(Method::GET, &uri, Some(&token), &[], None).await;
+    assert_eq!(reply.body["title"], "second");
+
+    let reply = app
+        .send(
+            Method::DELETE,
+            &format!("{uri}?is_soft=false"),
+            Some(&token),
+            &[("if-match", "\"1\"")],
+            None,
+        )
+        .await;
+    assert_eq!(reply.status, StatusCode::PRECONDITION_FAILED);
+    let reply = app
+        .send(
+            Method::DELETE,
+            &format!("{uri}?is_soft=false"),
+            Some(&token),
+            &[("if-match", "\"2\"")],
+            None,
+        )
+        .await;
+    assert_eq!(reply.status, StatusCode::OK);
+    let reply = app.send(Method::GET, &uri, Some(&token), &[], None).await;
+    assert_eq!(reply.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn trashed_tasks_only_come_back_by_restore() {
+    let app = TestApp::new();
+    let (token, _) = app.sign_up("alice").await;
+    let id = app.add_task(&token, "first").await;
+    let uri = format!("/tasks/{id}");
+
+    let reply = app
+        .send(Method::DELETE, &uri, Some(&token), &[], None)
+        .await;
+    assert_eq!(reply.status, StatusCode::OK);
+    let reply = app.send(Method::GET, &uri, Some(&token), &[], None).await;
+    assert_eq!(reply.status, StatusCode::NOT_FOUND);
+    let reply = app
+        .send(
+            Method::PUT,
+            &uri,
+            Some(&token),
+            &[],
+            Some(json!({ "title": "replaced" })),
+        )
+        .await;
+    assert_eq!(reply.status, StatusCode::NOT_FOUND);
+    let reply = app
+        .send(Method::GET, "/tasks/trash", Some(&token), &[], None)
+        .await;
+    assert_eq!(reply.body["items"][0]["task_id"], id);
+
+    let reply = app
+        .send(
+            Method::POST,
+            &format!("{uri}/restore"),
+            Some(&token),
+            &[],
+            None,
+        )
+        .await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert!(reply.body["deleted_at"].is_null());
+    let reply = app.send(Method::GET, &uri, Some(&token), &[], None).await;
+    assert_eq!(reply.status, StatusCode::OK);
+    assert_eq!(reply.body["title"], "first");
+}
+
+#[tokio::test]
+async fn tasks_of_other_users_are_not_found() {
+    let app = TestApp::new();
+    let (alice, _) = app.sign_up("alice").await;
+    let (bob, _) = app.sign_up("bob").await;
+    let id = app.add_task(&alice, "private").await;
+
+    let reply = app
+        .send(Method::GET, &format!("/tasks/{id}"), Some(&bob), &[], None)
+        .await;
+    assert_eq!(reply.status, StatusCode::NOT_FOUND);
+    let reply = app.send(Method::GET, "/tasks", Some(&bob), &[], None).await;
+    assert_eq!(reply.body["items"], json!([]));
+}
+
+#[tokio::test]
+async fn a_reused_refresh_token_ends_the_session() {
+    let app = TestApp::new();
+    let (token, refresh_token) = app.sign_up("alice").await;
+    let refresh = |refresh_token: String| {
+        app.send(
+            Method::POST,
+            "/users/refresh",
+            None,
+            &[],
+            Some(json!({ "refresh_token": refresh_token })),
+        )
+    };
+    let reply = refresh(refresh_token.clone()).await;
+    assert_eq!(reply.status, StatusCode::OK);
+    let next = reply.body["refresh_token"].as_str().unwrap().to_owned();
+
+    let reply = refresh(refresh_token).await;
+    assert_eq!(reply.status, StatusCode::UNAUTHORIZED);
+    // the whole session is gone, the newer token and the access token included
+    let reply = refresh(next).await;
+    assert_eq!(reply.status, StatusCode::UNAUTHORIZED);
+    let reply = app
+        .send(Method::GET, "/tasks", Some(&token), &[], None)
+        .await;
+    assert_eq!(reply.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn malformed_ids_and_queries_are_validation_errors() {
+    let app = TestApp::new();
+    let (token, _) = app.sign_up("alice").await;
+    let id = app.add_task(&token, "first").await;
+
+    for (method, uri) in [
+        (Method::GET, "/tasks/abc".to_owned()),
+        (Method::POST, "/tasks/abc/complete".to_owned()),
+        (Method::DELETE, "/users/me/api-keys/abc".to_owned()),
+        (Method::DELETE, format!("/tasks/{id}?is_soft=maybe")),
+    ] {
+        let reply = app.send(method, &uri, Some(&token), &[], None).await;
+        assert_eq!(reply.status, StatusCode::BAD_REQUEST, "{uri}");
+        assert_eq!(
+            reply.headers[header::CONTENT_TYPE],
+            "application/problem+json"
+        );
+        assert_eq!(reply.code(), "validation_failed", "{uri}");
+    }
+}
+
+#[tokio::test]
+async fn a_new_password_deletes_the_api_keys() {
+    let app = TestApp::new();
+    let (token, _) = app.sign_up("alice").await;
+    let reply = app
+        .send(
+            Method::POST,
+            "/users/me/api-keys",
+            Some(&token),
+            &[],
+            Some(json!({ "name": "ci", "scopes": ["tasks:read"] })),
+        )
+        .await;
+    assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+    let key = reply.body["key"].as_str().unwrap().to_owned();
+    let with_key = [("x-api-key", key.as_str())];
+    let reply = app.send(Method::GET, "/tasks", None, &with_key, None).await;
+    assert_eq!(reply.status, StatusCode::OK);
+
+    let reply = app
+        .send(
+            Method::POST,
+            "/users/me/password",
+            Some(&token),
+            &[],
+            Some(json!({ "old_password": PASSWORD, "new_password": "another secret" })),
+        )
+        .await;
+    assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+    assert_eq!(reply.body, json!({ "revoked": 1, "api_keys_deleted": 1 }));
+    let reply = app.send(Method::GET, "/tasks", None, &with_key, None).await;
+    assert_eq!(reply.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn passwords_are_limited_in_bytes() {
+    let app = TestApp::new();
+    let sign_up = |password: String| {
+        app.send(
+            Method::POST,
+            "/users",
+            None,
+            &[],
+            Some(json!({
+                "username": "alice",
+                "password": password,
+                "email": "alice@example.com",
+            })),
+        )
+    };
+    // 40 characters, 80 bytes
+    let reply = sign_up("é".repeat(40)).await;
+    assert_eq!(reply.status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        reply.body["errors"]["password"],
+        json!(["must have at most 72 bytes"])
+    );
+    let reply = sign_up("é".repeat(36)).await;
+    assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+}
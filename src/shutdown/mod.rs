@@ -0,0 +1,94 @@
+//! Graceful shutdown: a SIGTERM or ctrl-c stops accepting connections, in-flight requests
+//! are drained, and background tasks are told to stop through a cancellation token.
+//! Requests get their own token, cancelled only when the drain deadline passes.
+use std::future::Future;
+use tokio::time::Instant;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    token: CancellationToken,
+    requests: CancellationToken,
+    tracker: TaskTracker,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancelled as soon as shutdown starts. Background loops should select on it
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    /// Cancelled when the drain deadline passes. Long waits inside a request (e.g. for a txn
+    /// receipt) should select on it, so they can finish while the request drains
+    pub fn request_token(&self) -> CancellationToken {
+        self.requests.clone()
+    }
+
+    /// Cancel the request token at `deadline`
+    pub fn drain_until(&self, deadline: Instant) {
+        let requests = self.requests.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep_until(deadline).await;
+            requests.cancel();
+        });
+    }
+
+    /// Spawn background work that shutdown will wait for. The task receives a child token
+    pub fn spawn<F, Fut>(&self, task: F)
+    where
+        F: FnOnce(CancellationToken) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.tracker.spawn(task(self.token.child_token()));
+    }
+
+    /// Resolves once a shutdown signal arrives, then cancels the token
+    pub async fn triggered(&self) {
+        tokio::select! {
+            _ = signal() => self.token.cancel(),
+            _ = self.token.cancelled() => {}
+        }
+    }
+
+    /// Wait for spawned background tasks to finish, giving up at `deadline`
+    pub async fn wait_for_tasks(&self, deadline: Instant) {
+        self.tracker.close();
+        if tokio::time::timeout_at(deadline, self.tracker.wait())
+            .await
+            .is_err()
+        {
+            println!(
+                "shutdown: {} background task(s) still running after deadline",
+                self.tracker.len()
+            );
+        }
+    }
+}
+
+async fn signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install ctrl-c handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => println!("shutdown: ctrl-c received"),
+        _ = terminate => println!("shutdown: SIGTERM received"),
+    }
+}
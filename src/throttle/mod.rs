@@ -0,0 +1,241 @@
+//! Brute force protection of `/users/login`. Failed logins are counted per username and per
+//! client ip. Once a key reaches its limit every further failure locks it out, first for
+//! `LoginConfig::lockout`, then twice as long each time up to `LoginConfig::max_lockout`.
+//! A locked out key is rejected before the password is checked, so it costs no bcrypt run.
+//!
+//! The counters live in memory: they are per process and start over on a restart.
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::config::LoginConfig;
+
+// above this many keys, the ones without a recent failure are dropped on the next failure
+const PRUNE_THRESHOLD: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Username(String),
+    Ip(String),
+}
+
+#[derive(Debug)]
+struct Failures {
+    count: u32,
+    last: Instant,
+    locked_until: Option<Instant>,
+}
+
+pub struct LoginThrottle {
+    config: LoginConfig,
+    failures: Mutex<HashMap<Key, Failures>>,
+}
+
+impl LoginThrottle {
+    pub fn new(config: LoginConfig) -> Self {
+        Self {
+            config,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// How long until this username and ip may try again, None when they may try now.
+    /// The username is counted whether a user has it or not
+    pub fn check(&self, username: &str, ip: Option<&str>) -> Option<Duration> {
+        let now = Instant::now();
+        let failures = self.failures.lock().unwrap();
+        keys(username, ip)
+            .iter()
+            .filter_map(|key| failures.get(key)?.locked_until)
+            .filter_map(|locked_until| locked_until.checked_duration_since(now))
+            .max()
+    }
+
+    pub fn record_failure(&self, username: &str, ip: Option<&str>) {
+        let now = Instant::now();
+        let mut failures = self.failures.lock().unwrap();
+        if failures.len() > PRUNE_THRESHOLD {
+            failures.retain(|_, entry| now.duration_since(entry.last) < self.config.max_lockout);
+        }
+        for key in keys(username, ip) {
+            let limit = match key {
+                Key::Username(_) => self.config.max_attempts,
+                Key::Ip(_) => self.config.ip_max_attempts,
+            };
+            let entry = failures.entry(key).or_insert(Failures {
+                count: 0,
+                last: now,
+                locked_until: None,
+            });
+            // a key that stayed quiet for the longest lockout starts over
+            if now.duration_since(entry.last) >= self.config.max_lockout {
+                entry.count = 0;
+            }
+            entry.count += 1;
+            entry.last = now;
+            if entry.count >= limit {
+                entry.locked_until = Some(now + self.lockout(entry.count - limit));
+            }
+        }
+    }
+
+    /// Forget the failures of the username. Those of the ip stay, else an attacker could reset
+    /// them by logging into an account of their own now and then
+    pub fn record_success(&self, username: &str) {
+        self.failures
+            .lock()
+            .unwrap()
+            .remove(&Key::Username(username.to_owned()));
+    }
+
+    // lockout after `over` failures past the limit: lockout, 2 * lockout, 4 * lockout, ..
+    fn lockout(&self, over: u32) -> Duration {
+        self.config
+            .lockout
+            .checked_mul(2u32.saturating_pow(over))
+            .map_or(self.config.max_lockout, |lockout| {
+                lockout.min(self.config.max_lockout)
+            })
+    }
+}
+
+fn keys(username: &str, ip: Option<&str>) -> Vec<Key> {
+    let mut keys = vec![Key::Username(username.to_owned())];
+    if let Some(ip) = ip {
+        keys.push(Key::Ip(ip.to_owned()));
+    }
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCKOUT: Duration = Duration::from_secs(30);
+    const MAX_LOCKOUT: Duration = Duration::from_secs(100);
+
+    fn throttle() -> LoginThrottle {
+        LoginThrottle::new(LoginConfig {
+            max_attempts: 3,
+            ip_max_attempts: 5,
+            lockout: LOCKOUT,
+            max_lockout: MAX_LOCKOUT,
+        })
+    }
+
+    // the wait check reports, give or take the time the test took so far
+    fn assert_wait(throttle: &LoginThrottle, username: &str, ip: Option<&str>, wait: Duration) {
+        let left = throttle.check(username, ip).expect("locked out");
+        assert!(
+            left <= wait && wait - left < Duration::from_secs(1),
+            "{left:?} instead of {wait:?}"
+        );
+    }
+
+    #[test]
+    fn locks_out_at_max_attempts() {
+        let throttle = throttle();
+        for _ in 0..2 {
+            throttle.record_failure("alice", None);
+            assert_eq!(throttle.check("alice", None), None);
+        }
+        throttle.record_failure("alice", None);
+        assert_wait(&throttle, "alice", None, LOCKOUT);
+        assert_eq!(throttle.check("bob", None), None);
+    }
+
+    #[test]
+    fn lockout_doubles_up_to_the_max() {
+        let throttle = throttle();
+        for _ in 0..3 {
+            throttle.record_failure("alice", None);
+        }
+        assert_wait(&throttle, "alice", None, LOCKOUT);
+        throttle.record_failure("alice", None);
+        assert_wait(&throttle, "alice", None, LOCKOUT * 2);
+        throttle.record_failure("alice", None);
+        assert_wait(&throttle, "alice", None, MAX_LOCKOUT);
+        for _ in 0..40 {
+            throttle.record_failure("alice", None);
+        }
+        assert_wait(&throttle, "alice", None, MAX_LOCKOUT);
+    }
+
+    #[test]
+    fn the_ip_has_its_own_limit() {
+        let throttle = throttle();
+        for username in ["a", "b", "c", "d"] {
+            throttle.record_failure(username, Some("10.0.0.1"));
+        }
+        assert_eq!(throttle.check("e", Some("10.0.0.1")), None);
+        throttle.record_failure("e", Some("10.0.0.1"));
+        assert_wait(&throttle, "f", Some("10.0.0.1"), LOCKOUT);
+        assert_eq!(throttle.check("f", Some("10.0.0.2")), None);
+    }
+
+    #[test]
+    fn success_forgets_the_username_but_not_the_ip() {
+        let throttle = throttle();
+        for _ in 0..3 {
+            throttle.record_failure("alice", Some("10.0.0.1"));
+        }
+        assert!(throttle.check("alice", None).is_some());
+        throttle.record_success("alice");
+        assert_eq!(throttle.check("alice", None), None);
+        // two failures short of the ip limit, counting starts over for the username
+        throttle.record_failure("alice", Some("10.0.0.1"));
+        assert_eq!(throttle.check("alice", None), None);
+        throttle.record_failure("alice", Some("10.0.0.1"));
+        assert_wait(&throttle, "alice", Some("10.0.0.1"), LOCKOUT);
+        assert_eq!(throttle.check("alice", None), None);
+    }
+
+    #[test]
+    fn prunes_quiet_keys_past_the_threshold() {
+        let throttle = throttle();
+        let now = Instant::now();
+        {
+            let mut failures = throttle.failures.lock().unwrap();
+            for i in 0..=PRUNE_THRESHOLD {
+                // the quiet half failed longer than max_lockout ago
+                let quiet = i % 2 == 0;
+                let last = if quiet { now - MAX_LOCKOUT } else { now };
+                failures.insert(
+                    Key::Username(i.to_string()),
+                    Failures {
+                        count: 1,
+                        last,
+                        locked_until: None,
+                    },
+                );
+            }
+        }
+        throttle.record_failure("alice", None);
+        let failures = throttle.failures.lock().unwrap();
+        assert_eq!(failures.len(), PRUNE_THRESHOLD / 2 + 1);
+        assert!(failures.contains_key(&Key::Username("alice".to_owned())));
+        assert!(failures.contains_key(&Key::Username("1".to_owned())));
+        assert!(!failures.contains_key(&Key::Username("0".to_owned())));
+    }
+
+    #[test]
+    fn a_quiet_key_starts_over() {
+        let throttle = throttle();
+        for _ in 0..3 {
+            throttle.record_failure("alice", None);
+        }
+        let key = Key::Username("alice".to_owned());
+        throttle
+            .failures
+            .lock()
+            .unwrap()
+            .get_mut(&key)
+            .unwrap()
+            .last -= MAX_LOCKOUT;
+        throttle.record_failure("alice", None);
+        let failures = throttle.failures.lock().unwrap();
+        assert_eq!(failures[&key].count, 1);
+    }
+}
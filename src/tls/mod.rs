@@ -0,0 +1,99 @@
+//! HTTPS serving with rustls. The certificate is re-read when its files change on disk,
+//! and an optional plain HTTP listener redirects clients to the HTTPS port.
+use axum::{
+    extract::Host,
+    http::{StatusCode, Uri},
+    response::{IntoResponse, Redirect},
+    Router,
+};
+use axum_server_tls::{tls_rustls::RustlsConfig, Handle};
+use eyre::{Result, WrapErr};
+use std::{net::SocketAddr, time::SystemTime};
+
+use crate::{config::TlsConfig, shutdown::Shutdown};
+
+pub async fn load(tls: &TlsConfig) -> Result<RustlsConfig> {
+    RustlsConfig::from_pem_file(&tls.cert_path, &tls.key_path)
+        .await
+        .wrap_err_with(|| {
+            format!(
+                "failed to load tls certificate {:?} / key {:?}",
+                tls.cert_path, tls.key_path
+            )
+        })
+}
+
+/// Poll the cert and key modification times, reload both when either changes.
+/// A broken file keeps the current certificate in use
+pub fn watch(rustls: RustlsConfig, tls: TlsConfig, shutdown: &Shutdown) {
+    shutdown.spawn(move |token| async move {
+        let mut last_modified = modified(&tls);
+        let mut interval = tokio::time::interval(tls.reload_interval);
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            let current = modified(&tls);
+            if current == last_modified {
+                continue;
+            }
+            match rustls
+                .reload_from_pem_file(&tls.cert_path, &tls.key_path)
+                .await
+            {
+                Ok(()) => {
+                    println!("tls: certificate reloaded");
+                    last_modified = current;
+                }
+                Err(err) => {
+                    println!("tls: reloading certificate failed, keeping the old one: {err}")
+                }
+            }
+        }
+    });
+}
+
+fn modified(tls: &TlsConfig) -> Option<(SystemTime, SystemTime)> {
+    let cert = std::fs::metadata(&tls.cert_path).ok()?.modified().ok()?;
+    let key = std::fs::metadata(&tls.key_path).ok()?.modified().ok()?;
+    Some((cert, key))
+}
+
+/// Plain HTTP listener that sends every request to the same path on the HTTPS port
+pub fn redirect_http(addr: SocketAddr, https_port: u16, handle: Handle, shutdown: &Shutdown) {
+    let app = Router::new().fallback(move |Host(host): Host, uri: Uri| async move {
+        match https_uri(&host, &uri, https_port) {
+            Some(uri) => Redirect::permanent(&uri.to_string()).into_response(),
+            None => StatusCode::BAD_REQUEST.into_response(),
+        }
+    });
+    println!("Redirecting http://{addr} to https");
+    shutdown.spawn(move |_token| async move {
+        if let Err(err) = axum_server_tls::bind(addr)
+            .handle(handle)
+            .serve(app.into_make_service())
+            .await
+        {
+            println!("tls: http redirect listener failed: {err}");
+        }
+    });
+}
+
+fn https_uri(host: &str, uri: &Uri, https_port: u16) -> Option<Uri> {
+    // drop the port of the Host header, "[::1]:80" keeps its brackets
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => name,
+        _ => host,
+    };
+    let authority = if https_port == 443 {
+        host.to_owned()
+    } else {
+        format!("{host}:{https_port}")
+    };
+    let path = uri
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+    format!("https://{authority}{path}").parse().ok()
+}
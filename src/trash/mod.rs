@@ -0,0 +1,33 @@
+//! The trash of soft deleted tasks is emptied in the background: tasks deleted longer than
+//! `TrashConfig::retention` ago are deleted for good, checked every `purge_interval`.
+use chrono::Utc;
+
+use crate::{config::TrashConfig, repository::DynTaskRepository, shutdown::Shutdown};
+
+/// Nothing is spawned when the retention is off
+pub fn spawn_purger(tasks: DynTaskRepository, trash: TrashConfig, shutdown: &Shutdown) {
+    let Some(retention) = trash.retention else {
+        return;
+    };
+    shutdown.spawn(move |token| async move {
+        let mut interval = tokio::time::interval(trash.purge_interval);
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            // a retention too long to subtract keeps every task
+            let Some(before) = chrono::Duration::from_std(retention)
+                .ok()
+                .and_then(|retention| Utc::now().checked_sub_signed(retention))
+            else {
+                continue;
+            };
+            match tasks.purge_deleted(before.into()).await {
+                Ok(0) => {}
+                Ok(purged) => println!("trash: purged {purged} task(s)"),
+                Err(err) => println!("trash: purging failed: {err}"),
+            }
+        }
+    });
+}
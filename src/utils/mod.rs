@@ -1,92 +1,124 @@
-use axum::{http::StatusCode, response::IntoResponse, Json};
 use chrono::{Duration, Utc};
 use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::OnceLock;
+use uuid::Uuid;
 
-pub fn hash_password(password: String) -> Result<String, StatusCode> {
+use crate::{config::AuthConfig, error::AppError};
+
+pub fn hash_password(password: String, hash_cost: u32) -> Result<String, AppError> {
     //Set bcrypt hash cost to 14 or above to ensure enought time cost against hackers
-    let hashcost = dotenvy::var("HASHCOST").expect("HASHCOST not found in .env");
-    let hashcostu32 = hashcost.parse::<u32>().expect("HASHCOST not u32");
-    bcrypt::hash(password, hashcostu32).map_err(|_e| StatusCode::INTERNAL_SERVER_ERROR)
+    bcrypt::hash(password, hash_cost)
+        .map_err(|e| AppError::Internal(format!("hashing password: {e}")))
     //cost should be 1 ~ 31
     //bcrypt(cost, salt, password)
 }
-pub fn verify_password(password: String, hash: &str) -> Result<bool, StatusCode> {
-    bcrypt::verify(password, hash).map_err(|_e| StatusCode::INTERNAL_SERVER_ERROR)
+pub fn verify_password(password: String, hash: &str) -> Result<bool, AppError> {
+    bcrypt::verify(password, hash)
+        .map_err(|e| AppError::Internal(format!("verifying password: {e}")))
+}
+/// Check the password against a throwaway hash of the same cost and ignore the result, so a
+/// login for a missing user takes as long as one with a wrong password
+pub fn dummy_verify_password(password: String, hash_cost: u32) -> Result<(), AppError> {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    let hash = match DUMMY_HASH.get() {
+        Some(hash) => hash,
+        None => {
+            let hash = hash_password(make_random_token(), hash_cost)?;
+            DUMMY_HASH.get_or_init(|| hash)
+        }
+    };
+    verify_password(password, hash)?;
+    Ok(())
 }
 
 //https://crates.io/crates/jsonwebtoken
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
-    //aud: String, // Optional. Audience
-    exp: usize, // Required (validate_exp defaults to true in validation). Expiration time (as UTC timestamp)
-    iat: usize, // Optional. Issued at (as UTC timestamp)
-                //iss: String, // Optional. Issuer
-                //nbf: usize,  // Optional. Not Before (as UTC timestamp)
-                //sub: String, // Optional. Subject (whom token refers to)
+    pub sub: String, // Subject: the user id
+    pub iss: String, // Issuer
+    pub aud: String, // Audience
+    pub jti: String, // Unique token id
+    pub sid: String, // Session id, see the sessions table. Empty for API keys
+    pub roles: Vec<String>,
+    // Only for API keys, whose claims are made up by auth instead of read from a JWT
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Vec<String>>,
+    pub exp: usize, // Required (validate_exp defaults to true in validation). Expiration time (as UTC timestamp)
+    pub iat: usize, // Optional. Issued at (as UTC timestamp)
+                    //nbf: usize,  // Optional. Not Before (as UTC timestamp)
 }
-pub fn make_jwt() -> Result<String, StatusCode> {
+impl Claims {
+    /// The user id in `sub`
+    pub fn user_id(&self) -> Result<i32, AppError> {
+        self.sub
+            .parse()
+            .map_err(|_e| AppError::Unauthorized("invalid token".to_owned()))
+    }
+}
+
+pub fn make_jwt(
+    auth: &AuthConfig,
+    user_id: i32,
+    session_id: &str,
+    roles: Vec<String>,
+) -> Result<String, AppError> {
     let now = Utc::now();
     let iat = now.timestamp() as usize;
-    let duration = Duration::seconds(30);
+    let duration = Duration::seconds(auth.access_token_ttl.as_secs() as i64);
     let exp = (now + duration).timestamp() as usize;
-    let claim = Claims { exp, iat };
-
-    let secret = dotenvy::var("JWT_SECRET").expect("secret not found in .env");
+    let claim = Claims {
+        sub: user_id.to_string(),
+        iss: auth.jwt_issuer.clone(),
+        aud: auth.jwt_audience.clone(),
+        jti: Uuid::new_v4().to_string(),
+        sid: session_id.to_owned(),
+        roles,
+        scopes: None,
+        exp,
+        iat,
+    };
 
     let token = encode(
         &Header::default(),
         &claim,
-        &EncodingKey::from_secret(secret.as_ref()),
+        &EncodingKey::from_secret(auth.jwt_secret.as_ref()),
     )
-    .map_err(|_e| StatusCode::INTERNAL_SERVER_ERROR);
+    .map_err(|e| AppError::Internal(format!("making jwt: {e}")));
     token
 }
 
-pub fn verify_jwt(token: &str) -> Result<bool, AppError> {
+pub fn verify_jwt(token: &str, auth: &AuthConfig) -> Result<Claims, AppError> {
     //verify(password, hash)
-    let secret = dotenvy::var("JWT_SECRET").expect("secret key not found in .env");
-    let key = DecodingKey::from_secret(secret.as_bytes());
-    let validation = &Validation::new(Algorithm::HS256);
-    let _result =
-        decode::<Claims>(token, &key, validation).map_err(|error| match error.kind() {
+    let key = DecodingKey::from_secret(auth.jwt_secret.as_bytes());
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_issuer(&[&auth.jwt_issuer]);
+    validation.set_audience(&[&auth.jwt_audience]);
+    validation.set_required_spec_claims(&["exp", "sub", "iss", "aud"]);
+    let token_data =
+        decode::<Claims>(token, &key, &validation).map_err(|error| match error.kind() {
             jsonwebtoken::errors::ErrorKind::ExpiredSignature => {
                 println!("verify_jwt: token expired");
-                AppError::new(StatusCode::UNAUTHORIZED, "session expired. error 010")
+                AppError::SessionExpired
             }
             _ => {
                 println!("verify_jwt: token invalid");
-                AppError::new(StatusCode::INTERNAL_SERVER_ERROR, "error 011")
+                AppError::Unauthorized("invalid token".to_owned())
             }
         })?;
-    Ok(true)
+    Ok(token_data.claims)
 }
 
-pub struct AppError {
-    code: StatusCode,
-    message: String,
-}
-impl AppError {
-    pub fn new(code: StatusCode, message: impl Into<String>) -> Self {
-        Self {
-            code,
-            message: message.into(),
-        }
-    }
-}
-//Axum's trait
-impl IntoResponse for AppError {
-    fn into_response(self) -> axum::response::Response {
-        (
-            self.code,
-            Json(ResponseMessage {
-                message: self.message,
-            }),
-        )
-            .into_response()
-    }
+/// A random opaque token for refresh tokens and mailed links, 64 hex characters
+pub fn make_random_token() -> String {
+    [
+        Uuid::new_v4().simple().to_string(),
+        Uuid::new_v4().simple().to_string(),
+    ]
+    .concat()
 }
-#[derive(Debug, Serialize)]
-struct ResponseMessage {
-    message: String,
+/// Random tokens are stored as their sha256 only, they are too long to need salt or bcrypt
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
 }
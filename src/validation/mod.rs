@@ -0,0 +1,141 @@
+//! Request body validation. Handlers take `ValidatedJson<T>` instead of `Json<T>`, the body is
+//! parsed and checked against the `validator` rules of `T` before the handler runs:
+//!
+//! ```ignore
+//! pub async fn add_task(ValidatedJson(json): ValidatedJson<AddTask>, ...) -> Result<..., AppError>
+//! ```
+//!
+//! Broken rules answer 400 `validation_failed` with the messages keyed by field, a body that is
+//! no JSON or doesn't fit `T` answers 400 `validation_failed` as well. `ValidatedQuery<T>` does
+//! the same for the query string, `ValidatedPath<T>` for path segments such as `/tasks/:id`.
+use axum::{
+    async_trait,
+    body::HttpBody,
+    extract::{FromRequest, FromRequestParts, Json, Path, Query},
+    http::{request::Parts, Request},
+    BoxError,
+};
+use serde::de::DeserializeOwned;
+use std::borrow::Cow;
+use validator::{Validate, ValidationError};
+
+use crate::error::AppError;
+
+pub const USERNAME_MIN_LEN: usize = 3;
+pub const USERNAME_MAX_LEN: usize = 32;
+pub const PASSWORD_MIN_LEN: usize = 8;
+/// bcrypt only looks at the first 72 bytes, a longer password would match any with its prefix
+pub const PASSWORD_MAX_BYTES: usize = 72;
+
+/// Extractor for a JSON body that passes `T::validate`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidatedJson<T>(pub T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(request: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(request, state).await?;
+        value.validate()?;
+        Ok(Self(value))
+    }
+}
+
+/// Extractor for query parameters that pass `T::validate`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidatedQuery<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for ValidatedQuery<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(value) = Query::<T>::from_request_parts(parts, state).await?;
+        value.validate()?;
+        Ok(Self(value))
+    }
+}
+
+/// Extractor for path parameters, a segment that doesn't parse as `T` answers 400
+/// `validation_failed` instead of axum's plain text
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ValidatedPath<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for ValidatedPath<T>
+where
+    T: DeserializeOwned + Send,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(value) = Path::<T>::from_request_parts(parts, state).await?;
+        Ok(Self(value))
+    }
+}
+
+/// Usernames are 3 to 32 ASCII letters, digits, `_`, `-` or `.`, and start with a letter or
+/// digit. Usernames from before this rule keep working for login
+pub fn validate_username(username: &str) -> Result<(), ValidationError> {
+    let len = username.chars().count();
+    if !(USERNAME_MIN_LEN..=USERNAME_MAX_LEN).contains(&len) {
+        return Err(username_error(format!(
+            "must have {USERNAME_MIN_LEN} to {USERNAME_MAX_LEN} characters"
+        )));
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    {
+        return Err(username_error(
+            "may only contain letters, digits, `_`, `-` and `.`".to_owned(),
+        ));
+    }
+    if !username.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        return Err(username_error(
+            "must start with a letter or digit".to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+fn username_error(message: String) -> ValidationError {
+    field_error("username", message)
+}
+
+/// Passwords have at least 8 characters and at most 72 bytes, so a password of non ASCII
+/// characters gets fewer than 72 characters
+pub fn validate_password(password: &str) -> Result<(), ValidationError> {
+    if password.chars().count() < PASSWORD_MIN_LEN {
+        return Err(field_error(
+            "password",
+            format!("must have at least {PASSWORD_MIN_LEN} characters"),
+        ));
+    }
+    if password.len() > PASSWORD_MAX_BYTES {
+        return Err(field_error(
+            "password",
+            format!("must have at most {PASSWORD_MAX_BYTES} bytes"),
+        ));
+    }
+    Ok(())
+}
+
+fn field_error(code: &'static str, message: String) -> ValidationError {
+    let mut error = ValidationError::new(code);
+    error.message = Some(Cow::Owned(message));
+    error
+}